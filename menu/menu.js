@@ -9,10 +9,14 @@ function status(f) {
             for (var i = 0; i < stations.length; i++) {
                 var idparts = stations[i]['listenurl'].split('/');
                 var id = idparts[idparts.length - 1];
-                var nameparts = (stations[i]['title'] || '').split(' - ');
-                var name = nameparts[0];
-                if (nameparts.length <= 1) {
-                    name = id;
+                // sprunk says the station name, icecast only has the title
+                var name = stations[i]['server_name'];
+                if (!name) {
+                    var nameparts = (stations[i]['title'] || '').split(' - ');
+                    name = nameparts[0];
+                    if (nameparts.length <= 1) {
+                        name = id;
+                    }
                 }
                 
                 stations[i]['name'] = name;
@@ -31,7 +35,13 @@ function update() {
             if (current != stations[i]['id'])
                 continue;
             var np = document.getElementById('nowplaying');
-            np.innerHTML = stations[i]['title'].replace(/ - /g, '<br />');
+            var segment = stations[i]['segment'];
+            if (segment) {
+                var lines = [stations[i]['name'], segment['artist'] || segment['zone'], segment['title']];
+                np.innerHTML = lines.filter(l => l).join('<br />');
+            } else {
+                np.innerHTML = stations[i]['title'].replace(/ - /g, '<br />');
+            }
             document.title = stations[i]['title'];
         }
     });
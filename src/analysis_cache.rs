@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rayon::prelude::*;
+
+use crate::source;
+
+// what's been measured about media files: integrated loudness, and where
+// silence was trimmed at one threshold. keyed by file path, size and mtime.
+// on disk this is an append-only list of tab-separated lines:
+//   lufs size mtime path
+//   trim threshold start end size mtime path
+// where later lines replace earlier ones. replaced lines are
+// cleaned out when the cache is opened.
+#[derive(Clone, Debug)]
+pub struct AnalysisCache {
+    inner: Arc<Mutex<CacheData>>,
+}
+
+#[derive(Debug)]
+struct CacheData {
+    path: Option<PathBuf>,
+    entries: HashMap<PathBuf, Entry>,
+    trims: HashMap<PathBuf, TrimEntry>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    size: u64,
+    mtime: u128,
+    lufs: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TrimEntry {
+    size: u64,
+    mtime: u128,
+    threshold: f32,
+    // kept frames, as [start, end)
+    start: u64,
+    end: u64,
+}
+
+impl AnalysisCache {
+    // a cache that only lives in memory
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CacheData {
+                path: None,
+                entries: HashMap::new(),
+                trims: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn open<P>(path: P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut entries = HashMap::new();
+        let mut trims = HashMap::new();
+        let mut lines = 0;
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    lines += 1;
+                    // bad lines are just skipped, they'll be re-measured
+                    if let Some(rest) = line.strip_prefix("trim\t") {
+                        if let Some((key, entry)) = Self::parse_trim_line(rest) {
+                            trims.insert(key, entry);
+                        }
+                    } else if let Some((key, entry)) = Self::parse_line(line) {
+                        entries.insert(key, entry);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        // rewrite it without the lines that were replaced or bad
+        let data = CacheData {
+            path: Some(path.as_ref().to_owned()),
+            entries,
+            trims,
+        };
+        if lines > data.entries.len() + data.trims.len() {
+            Self::compact(path.as_ref(), &data)?;
+        }
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(data)),
+        })
+    }
+
+    // replace the file with just these entries, all at once
+    fn compact(path: &Path, data: &CacheData) -> anyhow::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut f = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        for (key, e) in data.entries.iter() {
+            if let Some(keystr) = key.to_str() {
+                writeln!(f, "{}\t{}\t{}\t{}", e.lufs, e.size, e.mtime, keystr)?;
+            }
+        }
+        for (key, e) in data.trims.iter() {
+            if let Some(keystr) = key.to_str() {
+                writeln!(f, "{}", Self::trim_line(e, keystr))?;
+            }
+        }
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // is this cache kept on disk?
+    pub fn is_persistent(&self) -> bool {
+        self.inner.lock().map(|d| d.path.is_some()).unwrap_or(false)
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, Entry)> {
+        let mut parts = line.splitn(4, '\t');
+        let lufs = parts.next()?.parse().ok()?;
+        let size = parts.next()?.parse().ok()?;
+        let mtime = parts.next()?.parse().ok()?;
+        let path = parts.next()?;
+        Some((path.into(), Entry { size, mtime, lufs }))
+    }
+
+    fn parse_trim_line(line: &str) -> Option<(PathBuf, TrimEntry)> {
+        let mut parts = line.splitn(6, '\t');
+        let threshold = parts.next()?.parse().ok()?;
+        let start = parts.next()?.parse().ok()?;
+        let end = parts.next()?.parse().ok()?;
+        let size = parts.next()?.parse().ok()?;
+        let mtime = parts.next()?.parse().ok()?;
+        let path = parts.next()?;
+        let entry = TrimEntry {
+            size,
+            mtime,
+            threshold,
+            start,
+            end,
+        };
+        Some((path.into(), entry))
+    }
+
+    fn trim_line(e: &TrimEntry, keystr: &str) -> String {
+        format!(
+            "trim\t{}\t{}\t{}\t{}\t{}\t{}",
+            e.threshold, e.start, e.end, e.size, e.mtime, keystr
+        )
+    }
+
+    fn stat(path: &Path) -> anyhow::Result<(PathBuf, u64, u128)> {
+        let key = std::fs::canonicalize(path)?;
+        let meta = std::fs::metadata(&key)?;
+        let mtime = meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos();
+        Ok((key, meta.len(), mtime))
+    }
+
+    pub fn get<P>(&self, path: P) -> Option<f32>
+    where
+        P: AsRef<Path>,
+    {
+        let (key, size, mtime) = Self::stat(path.as_ref()).ok()?;
+        let data = self.inner.lock().ok()?;
+        data.entries
+            .get(&key)
+            .filter(|e| e.size == size && e.mtime == mtime)
+            .map(|e| e.lufs)
+    }
+
+    pub fn insert<P>(&self, path: P, lufs: f32) -> anyhow::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let (key, size, mtime) = Self::stat(path.as_ref())?;
+        let mut data = self
+            .inner
+            .lock()
+            .map_err(|_| anyhow::anyhow!("analysis cache poisoned"))?;
+
+        if let Some(ref cachepath) = data.path {
+            // paths we can't write down are only cached in memory
+            if let Some(keystr) = key.to_str() {
+                let mut f = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(cachepath)?;
+                writeln!(f, "{}\t{}\t{}\t{}", lufs, size, mtime, keystr)?;
+            }
+        }
+
+        data.entries.insert(key, Entry { size, mtime, lufs });
+        Ok(())
+    }
+
+    // the frames kept when trimming at this threshold, as [start, end)
+    pub fn get_trim<P>(&self, path: P, threshold: f32) -> Option<(u64, u64)>
+    where
+        P: AsRef<Path>,
+    {
+        let (key, size, mtime) = Self::stat(path.as_ref()).ok()?;
+        let data = self.inner.lock().ok()?;
+        data.trims
+            .get(&key)
+            .filter(|e| e.size == size && e.mtime == mtime && e.threshold == threshold)
+            .map(|e| (e.start, e.end))
+    }
+
+    pub fn insert_trim<P>(
+        &self,
+        path: P,
+        threshold: f32,
+        (start, end): (u64, u64),
+    ) -> anyhow::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let (key, size, mtime) = Self::stat(path.as_ref())?;
+        let mut data = self
+            .inner
+            .lock()
+            .map_err(|_| anyhow::anyhow!("analysis cache poisoned"))?;
+
+        let entry = TrimEntry {
+            size,
+            mtime,
+            threshold,
+            start,
+            end,
+        };
+        if let Some(ref cachepath) = data.path {
+            if let Some(keystr) = key.to_str() {
+                let mut f = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(cachepath)?;
+                writeln!(f, "{}", Self::trim_line(&entry, keystr))?;
+            }
+        }
+
+        data.trims.insert(key, entry);
+        Ok(())
+    }
+
+    // measure the loudness of everything not already in the cache, in parallel,
+    // calling back with each path as it finishes
+    pub fn scan<P, F>(&self, paths: &[P], f: F) -> Vec<(PathBuf, anyhow::Result<f32>)>
+    where
+        P: AsRef<Path> + Sync,
+        F: Fn(&Path, &anyhow::Result<f32>) + Sync,
+    {
+        paths
+            .par_iter()
+            .filter(|p| self.get(p).is_none())
+            .map(|p| {
+                let p = p.as_ref();
+                let r = std::fs::File::open(p)
+                    .map_err(|e| e.into())
+                    .and_then(source::Media::new)
+                    .and_then(|mut m| source::measure_lufs(&mut m))
+                    .and_then(|lufs| self.insert(p, lufs).map(|_| lufs));
+                f(p, &r);
+                (p.to_owned(), r)
+            })
+            .collect()
+    }
+}
+
+impl Default for AnalysisCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AnalysisCache;
+    use std::path::PathBuf;
+
+    // a fresh directory with a media file in it, and where to keep a cache
+    fn setup(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("sprunk-cache-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let media = dir.join("song.wav");
+        std::fs::write(&media, b"not really audio").unwrap();
+        let cache = dir.join("cache.txt");
+        (dir, media, cache)
+    }
+
+    #[test]
+    fn round_trips() {
+        let (dir, media, path) = setup("round-trip");
+        let cache = AnalysisCache::open(&path).unwrap();
+        assert!(cache.is_persistent());
+        cache.insert(&media, -12.5).unwrap();
+        cache.insert_trim(&media, -50.0, (10, 20)).unwrap();
+
+        let cache = AnalysisCache::open(&path).unwrap();
+        assert_eq!(cache.get(&media), Some(-12.5));
+        assert_eq!(cache.get_trim(&media, -50.0), Some((10, 20)));
+        assert_eq!(cache.get_trim(&media, -40.0), None);
+
+        // a changed file isn't believed any more
+        std::fs::write(&media, b"different audio, longer").unwrap();
+        assert_eq!(cache.get(&media), None);
+        assert_eq!(cache.get_trim(&media, -50.0), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compacts_on_open() {
+        let (dir, media, path) = setup("compact");
+        let cache = AnalysisCache::open(&path).unwrap();
+        cache.insert(&media, -20.0).unwrap();
+        cache.insert(&media, -10.0).unwrap();
+        cache.insert_trim(&media, -50.0, (0, 5)).unwrap();
+        cache.insert_trim(&media, -50.0, (1, 4)).unwrap();
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("garbage\ttrim\n");
+        std::fs::write(&path, contents).unwrap();
+
+        // only the latest of each is kept
+        let cache = AnalysisCache::open(&path).unwrap();
+        assert_eq!(cache.get(&media), Some(-10.0));
+        assert_eq!(cache.get_trim(&media, -50.0), Some((1, 4)));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(!dir.join("cache.txt.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use crate::playlist::Playlist;
+
+use tokio::sync::{mpsc, oneshot};
+
+// things a running station can be asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // cut off whatever is playing and move on
+    Skip,
+    // hold the station where it is, silent, until resumed
+    Pause,
+    Resume,
+    // play a song (or zone, for wow stations) next, found by name
+    Queue(String),
+    // play an ad break next
+    Ad,
+    // play a station identification next
+    Id,
+}
+
+pub struct Request {
+    pub command: Command,
+    reply: oneshot::Sender<anyhow::Result<()>>,
+}
+
+// send commands to a station, from any thread
+#[derive(Debug, Clone)]
+pub struct Control {
+    sender: mpsc::UnboundedSender<Request>,
+    playlist: Playlist,
+}
+
+// the station's end of a Control
+#[derive(Debug)]
+pub struct ControlReceiver {
+    receiver: mpsc::UnboundedReceiver<Request>,
+    playlist: Playlist,
+}
+
+pub fn channel() -> (Control, ControlReceiver) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let playlist = Playlist::new();
+    (
+        Control {
+            sender,
+            playlist: playlist.clone(),
+        },
+        ControlReceiver { receiver, playlist },
+    )
+}
+
+// wait for the next request, forever if there's no control at all
+pub async fn next_request(control: &mut Option<ControlReceiver>) -> Request {
+    match control {
+        Some(control) => control.recv().await,
+        None => futures_lite::future::pending().await,
+    }
+}
+
+impl Command {
+    pub fn parse(name: &str, arg: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "skip" => Command::Skip,
+            "pause" => Command::Pause,
+            "resume" => Command::Resume,
+            "queue" => {
+                let query = arg.trim();
+                if query.is_empty() {
+                    anyhow::bail!("queue needs something to play");
+                }
+                Command::Queue(query.to_owned())
+            }
+            "ad" => Command::Ad,
+            "id" => Command::Id,
+            _ => anyhow::bail!("unrecognized command: {:?}", name),
+        })
+    }
+}
+
+impl Request {
+    pub fn reply(self, result: anyhow::Result<()>) {
+        // nobody waiting is fine
+        let _ = self.reply.send(result);
+    }
+}
+
+impl Control {
+    // send a command, and wait for the station to handle it
+    pub async fn send(&self, command: Command) -> anyhow::Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(Request { command, reply })
+            .map_err(|_| anyhow::anyhow!("station is not running"))?;
+        response
+            .await
+            .map_err(|_| anyhow::anyhow!("station stopped"))?
+    }
+
+    // what the station has played, and will play
+    pub fn playlist(&self) -> &Playlist {
+        &self.playlist
+    }
+
+    // do these both send to the same station?
+    pub fn same_station(&self, other: &Control) -> bool {
+        self.sender.same_channel(&other.sender)
+    }
+}
+
+impl ControlReceiver {
+    // the station keeps this up to date as it plays
+    pub fn playlist(&self) -> &Playlist {
+        &self.playlist
+    }
+
+    // wait for the next request, forever if every Control is gone
+    pub async fn recv(&mut self) -> Request {
+        match self.receiver.recv().await {
+            Some(request) => request,
+            None => futures_lite::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{source, Source};
+
+use std::path::Path;
+
+// seconds per energy measurement
+const WINDOW: f32 = 0.05;
+// seconds of windows averaged together when looking for levels
+const SMOOTH: f32 = 1.0;
+// the vocal range, roughly, in Hz
+const BAND_LOW: f32 = 300.0;
+const BAND_HIGH: f32 = 3000.0;
+// fraction of the song's usual energy where the intro ends
+const PRE_LEVEL: f32 = 0.5;
+// fraction of the song's usual energy where the outro starts
+const POST_LEVEL: f32 = 0.25;
+// which quantile of window energies counts as usual
+const TYPICAL: f32 = 0.75;
+
+// guesses at where a song's intro ends and its outro starts, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cues {
+    pub pre: f32,
+    pub post: f32,
+}
+
+// measures energy in the vocal range over time, one sample at a time
+pub struct CueDetector {
+    samplerate: f32,
+    // one-pole filter coefficients and state
+    high_alpha: f32,
+    low_alpha: f32,
+    high_in: f32,
+    high_out: f32,
+    low_out: f32,
+    window: usize,
+    sum: f32,
+    count: usize,
+    energies: Vec<f32>,
+}
+
+impl CueDetector {
+    pub fn new(samplerate: f32) -> Self {
+        let dt = 1.0 / samplerate;
+        let high_rc = 1.0 / (2.0 * std::f32::consts::PI * BAND_LOW);
+        let low_rc = 1.0 / (2.0 * std::f32::consts::PI * BAND_HIGH);
+        Self {
+            samplerate,
+            high_alpha: high_rc / (high_rc + dt),
+            low_alpha: dt / (low_rc + dt),
+            high_in: 0.0,
+            high_out: 0.0,
+            low_out: 0.0,
+            window: ((WINDOW * samplerate) as usize).max(1),
+            sum: 0.0,
+            count: 0,
+            energies: Vec::new(),
+        }
+    }
+
+    // add one mono sample
+    pub fn push(&mut self, sample: f32) {
+        self.high_out = self.high_alpha * (self.high_out + sample - self.high_in);
+        self.high_in = sample;
+        self.low_out += self.low_alpha * (self.high_out - self.low_out);
+
+        self.sum += self.low_out * self.low_out;
+        self.count += 1;
+        if self.count == self.window {
+            self.energies.push(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    pub fn finish(&self) -> Cues {
+        let length = (self.energies.len() * self.window + self.count) as f32 / self.samplerate;
+        let energies = &self.energies;
+        let smooth = ((SMOOTH / WINDOW) as usize).max(1);
+        if energies.len() < smooth {
+            return Cues {
+                pre: 0.0,
+                post: length,
+            };
+        }
+
+        // what the song usually sounds like, ignoring silence
+        let mut sorted: Vec<f32> = energies.iter().copied().filter(|e| *e > 0.0).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let Some(typical) = sorted
+            .get((sorted.len() as f32 * TYPICAL) as usize)
+            .or(sorted.last())
+            .copied()
+        else {
+            return Cues {
+                pre: 0.0,
+                post: length,
+            };
+        };
+        let average = |range: &[f32]| range.iter().sum::<f32>() / range.len() as f32;
+
+        // both of these err early for pre and late for post, so that
+        // voiceovers stay clear of the song itself.
+        // the intro ends once the next second is up to level
+        let pre = energies
+            .windows(smooth)
+            .position(|w| average(w) >= PRE_LEVEL * typical)
+            .unwrap_or(0);
+
+        // the outro starts after the last second that was up to level
+        let post = energies
+            .windows(smooth)
+            .rposition(|w| average(w) >= POST_LEVEL * typical)
+            .map(|i| i + smooth)
+            .unwrap_or(energies.len());
+
+        let pre = pre as f32 * WINDOW;
+        let post = (post as f32 * WINDOW).min(length);
+        Cues {
+            pre: pre.min(post),
+            post,
+        }
+    }
+}
+
+// listen to a whole source and guess its cue points
+pub fn detect_cues<S>(source: &mut S) -> anyhow::Result<Cues>
+where
+    S: Source,
+{
+    let channels = source.channels() as usize;
+    let mut detector = CueDetector::new(source.samplerate());
+    let mut buffer = vec![0.0; source.samplerate() as usize * channels];
+    loop {
+        let amt = source.fill(&mut buffer)?;
+        if amt == 0 {
+            break;
+        }
+        for frame in buffer[..amt].chunks(channels) {
+            detector.push(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+    Ok(detector.finish())
+}
+
+// decode a whole media file and guess its cue points
+pub fn detect_file_cues(path: &Path) -> anyhow::Result<Cues> {
+    detect_cues(&mut source::Media::new(std::fs::File::open(path)?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::CueDetector;
+
+    #[test]
+    fn quiet_intro_and_outro() {
+        // 2s of quiet, 6s of loud, 2s of quiet
+        let samplerate = 8000.0;
+        let mut detector = CueDetector::new(samplerate);
+        for i in 0..(10.0 * samplerate) as usize {
+            let t = i as f32 / samplerate;
+            let level = if (2.0..8.0).contains(&t) { 1.0 } else { 0.05 };
+            detector.push(level * (2.0 * std::f32::consts::PI * 1000.0 * t).sin());
+        }
+
+        // guesses should err on the side of not talking over the song
+        let cues = detector.finish();
+        assert!(cues.pre > 1.0 && cues.pre <= 2.0, "pre was {}", cues.pre);
+        assert!(
+            cues.post >= 8.0 && cues.post < 9.0,
+            "post was {}",
+            cues.post
+        );
+    }
+}
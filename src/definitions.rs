@@ -1,8 +1,12 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+use rayon::prelude::*;
 use strict_yaml_rust::{StrictYaml, StrictYamlLoader};
 
 use crate::normalize::normalize;
+use crate::source::Ducking;
+use crate::Cues;
 
 #[derive(Debug, Clone)]
 pub struct Definitions {
@@ -19,6 +23,23 @@ pub struct Definitions {
     pub news: Vec<PathBuf>,
     pub intro: Vec<Intro>,
     pub music: Vec<Song>,
+    pub clock: Option<Vec<ClockSegment>>,
+    pub crossfade: Option<f32>,
+    // silence threshold in dBFS, for trimming items
+    pub trim: Option<f32>,
+    // how music dips under voiceovers
+    pub ducking: Option<Ducking>,
+    // song lengths in seconds, kept across reloads so each is only read once
+    lengths: HashMap<PathBuf, f32>,
+    // guessed cue points, kept the same way
+    cues: HashMap<PathBuf, Cues>,
+}
+
+// song lengths and guessed cue points, found by listening to songs
+#[derive(Debug, Default)]
+pub(crate) struct SongAnalysis {
+    lengths: HashMap<PathBuf, f32>,
+    cues: HashMap<PathBuf, Cues>,
 }
 
 #[derive(Debug, Clone)]
@@ -38,8 +59,63 @@ pub struct Intro {
 pub struct Song {
     pub path: PathBuf,
     pub metadata: Metadata,
-    pub pre: f32,
-    pub post: f32,
+    // when missing, these are guessed from the audio when played
+    pub pre: Option<f32>,
+    pub post: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentKind {
+    Music,
+    Ad,
+    News,
+    Id,
+    Mono,
+    // wow stations only
+    Zone,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClockSegment {
+    pub kind: SegmentKind,
+    pub count: u32,
+    pub chance: f32,
+}
+
+impl SegmentKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SegmentKind::Music => "music",
+            SegmentKind::Ad => "ad",
+            SegmentKind::News => "news",
+            SegmentKind::Id => "id",
+            SegmentKind::Mono => "mono",
+            SegmentKind::Zone => "zone",
+        }
+    }
+
+    // for display, when there's nothing more specific
+    pub fn label(&self) -> &'static str {
+        match self {
+            SegmentKind::Music => "Music",
+            SegmentKind::Ad => "Advertisement",
+            SegmentKind::News => "News",
+            SegmentKind::Id => "Identification",
+            SegmentKind::Mono => "Monologue",
+            SegmentKind::Zone => "Zone",
+        }
+    }
+
+    fn parse(kind: &str) -> anyhow::Result<Self> {
+        Ok(match kind.to_lowercase().as_ref() {
+            "music" => SegmentKind::Music,
+            "ad" => SegmentKind::Ad,
+            "news" => SegmentKind::News,
+            "id" => SegmentKind::Id,
+            "mono" | "solo" => SegmentKind::Mono,
+            _ => anyhow::bail!("unrecognized segment type: {:?}", kind),
+        })
+    }
 }
 
 impl Definitions {
@@ -69,20 +145,250 @@ impl Definitions {
             news: vec![],
             intro: vec![],
             music: vec![],
+            clock: None,
+            crossfade: None,
+            trim: None,
+            ducking: None,
+            lengths: HashMap::new(),
+            cues: HashMap::new(),
+        }
+    }
+
+    // the format used when no clock is defined:
+    // 12 songs, an ad, id and monologue, 12 songs, news, id and monologue
+    pub fn default_clock() -> Vec<ClockSegment> {
+        use SegmentKind::*;
+        let mut clock = vec![];
+        for break_kind in [Ad, News] {
+            for (kind, count) in [(Music, 12), (break_kind, 1), (Id, 1), (Mono, 1)] {
+                clock.push(ClockSegment {
+                    kind,
+                    count,
+                    chance: 1.0,
+                });
+            }
         }
+        clock
     }
 
     pub fn reload(&mut self) -> anyhow::Result<()> {
-        let mut new = Definitions::empty();
-        for path in self.paths.iter() {
-            new.merge(Definitions::load_one(path)?);
+        let mut problems = vec![];
+        let mut new = Definitions::load_all(&self.paths, &mut problems)?;
+        if let Some(problem) = problems.into_iter().next() {
+            return Err(problem);
         }
+        // out of range cues are fixed here, and reported by lint.
+        // only songs already listened to are checked, so this stays quick.
+        new.lengths = std::mem::take(&mut self.lengths);
+        new.cues = std::mem::take(&mut self.cues);
+        new.use_cues();
         std::mem::swap(&mut new.paths, &mut self.paths);
         *self = new;
         Ok(())
     }
 
-    fn load_one(path: &PathBuf) -> anyhow::Result<Self> {
+    // the directory media in this definitions file is relative to
+    pub fn media_prefix(path: &Path) -> anyhow::Result<PathBuf> {
+        let contents = std::fs::read_to_string(path)?;
+        let datawhole = StrictYamlLoader::load_from_str(&contents)?;
+        let data = datawhole
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("could not get definition document"))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let prefix = Self::get_str(data, "prefix")?.unwrap_or(".");
+        Ok(normalize(&base.join(prefix)))
+    }
+
+    // load these definitions and report every problem found, not just
+    // the first. fails only if they can't be read at all.
+    pub fn lint<PI, P>(paths: PI) -> anyhow::Result<Vec<anyhow::Error>>
+    where
+        PI: Iterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let paths: Vec<PathBuf> = paths.map(|p| p.as_ref().to_owned()).collect();
+        let mut problems = vec![];
+        let mut defs = Definitions::load_all(&paths, &mut problems)?;
+
+        for intro in defs.unmatched_intros() {
+            problems.push(anyhow::anyhow!(
+                "intro does not match any song: {:?}",
+                intro.path
+            ));
+        }
+
+        problems.extend(defs.measure_lengths());
+        problems.extend(defs.clamp_cues());
+
+        // the same file listed twice plays twice as often
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicated = std::collections::HashSet::new();
+        for path in defs.all_paths() {
+            if !seen.insert(path) && duplicated.insert(path) {
+                problems.push(anyhow::anyhow!("file listed more than once: {:?}", path));
+            }
+        }
+
+        let clock = defs
+            .clock
+            .clone()
+            .unwrap_or_else(Definitions::default_clock);
+        let mut missing = std::collections::HashSet::new();
+        for segment in clock.iter() {
+            if segment.count > 0
+                && segment.chance > 0.0
+                && !defs.has_segment(segment.kind)
+                && missing.insert(segment.kind)
+            {
+                problems.push(anyhow::anyhow!(
+                    "clock plays {} but there is nothing to play",
+                    segment.kind.name()
+                ));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    fn load_all(paths: &[PathBuf], problems: &mut Vec<anyhow::Error>) -> anyhow::Result<Self> {
+        let mut new = Definitions::empty();
+        for path in paths.iter() {
+            new.merge(Definitions::load_one(path, problems)?);
+        }
+        Ok(new)
+    }
+
+    // songs not listened to yet, and whether their cue points are wanted
+    pub(crate) fn unanalyzed(&self) -> Vec<(PathBuf, bool)> {
+        let mut wanted: HashMap<&PathBuf, bool> = HashMap::new();
+        for song in self.music.iter() {
+            let cues =
+                (song.pre.is_none() || song.post.is_none()) && !self.cues.contains_key(&song.path);
+            if cues || !self.lengths.contains_key(&song.path) {
+                *wanted.entry(&song.path).or_default() |= cues;
+            }
+        }
+        wanted.into_iter().map(|(p, c)| (p.clone(), c)).collect()
+    }
+
+    // listen to these songs in parallel, guessing cue points where asked.
+    // this is slow, so keep it off the audio task. songs that can't be
+    // read are left out.
+    pub(crate) fn analyze(songs: Vec<(PathBuf, bool)>) -> SongAnalysis {
+        let found: Vec<_> = songs
+            .into_par_iter()
+            .map(|(path, cues)| {
+                let length = Self::media_length(&path).ok();
+                let cues = cues.then(|| crate::detect_file_cues(&path).ok()).flatten();
+                (path, length, cues)
+            })
+            .collect();
+
+        let mut analysis = SongAnalysis::default();
+        for (path, length, cues) in found {
+            if let Some(length) = length {
+                analysis.lengths.insert(path.clone(), length);
+            }
+            if let Some(cues) = cues {
+                analysis.cues.insert(path, cues);
+            }
+        }
+        analysis
+    }
+
+    // remember what was found, and use it from now on
+    pub(crate) fn add_analysis(&mut self, analysis: SongAnalysis) {
+        self.lengths.extend(analysis.lengths);
+        self.cues.extend(analysis.cues);
+        self.use_cues();
+    }
+
+    // fill in missing cue points with guesses, and keep them all in range
+    fn use_cues(&mut self) {
+        for song in self.music.iter_mut() {
+            if let Some(cues) = self.cues.get(&song.path) {
+                song.pre = song.pre.or(Some(cues.pre));
+                song.post = song.post.or(Some(cues.post));
+            }
+        }
+        self.clamp_cues();
+    }
+
+    // read the length of songs not seen before, in parallel, since there
+    // can be a lot of them. says which couldn't be read.
+    fn measure_lengths(&mut self) -> Vec<anyhow::Error> {
+        let lengths: Vec<_> = self
+            .music
+            .par_iter()
+            .filter(|song| !self.lengths.contains_key(&song.path))
+            .map(|song| (song.path.clone(), Self::media_length(&song.path)))
+            .collect();
+
+        let mut problems = vec![];
+        for (path, length) in lengths {
+            match length {
+                Ok(length) => {
+                    self.lengths.insert(path, length);
+                }
+                Err(e) => problems.push(e.context(format!("could not read {:?}", path))),
+            }
+        }
+        problems
+    }
+
+    // pull song cue points back inside the audio and in order, and say
+    // which were out. songs of unknown length are only put in order.
+    fn clamp_cues(&mut self) -> Vec<anyhow::Error> {
+        let mut problems = vec![];
+        for song in self.music.iter_mut() {
+            if let Some(&length) = self.lengths.get(&song.path) {
+                if let Some(pre) = song.pre.filter(|pre| *pre > length) {
+                    problems.push(anyhow::anyhow!(
+                        "song pre {} is past the end at {}: {:?}",
+                        pre,
+                        length,
+                        song.path
+                    ));
+                    song.pre = Some(length);
+                }
+                if let Some(post) = song.post.filter(|post| *post > length) {
+                    problems.push(anyhow::anyhow!(
+                        "song post {} is past the end at {}: {:?}",
+                        post,
+                        length,
+                        song.path
+                    ));
+                    song.post = Some(length);
+                }
+            }
+
+            if let (Some(pre), Some(post)) = (song.pre, song.post) {
+                if pre > post {
+                    problems.push(anyhow::anyhow!(
+                        "song pre {} is after post {}: {:?}",
+                        pre,
+                        post,
+                        song.path
+                    ));
+                    song.pre = Some(post);
+                }
+            }
+        }
+        problems
+    }
+
+    // length of a media file, in seconds
+    fn media_length(path: &Path) -> anyhow::Result<f32> {
+        use crate::Source;
+        let media = crate::source::Media::new(std::fs::File::open(path)?)?;
+        let frames = media
+            .len()
+            .ok_or_else(|| anyhow::anyhow!("unknown length"))?;
+        Ok(frames as f32 / media.samplerate())
+    }
+
+    // missing media is skipped and added to problems
+    fn load_one(path: &PathBuf, problems: &mut Vec<anyhow::Error>) -> anyhow::Result<Self> {
         let mut new = Definitions::empty();
         let base = path.parent().unwrap_or(Path::new("."));
         let contents = std::fs::read_to_string(&path)?;
@@ -107,6 +413,10 @@ impl Definitions {
                 "news",
                 "intro",
                 "music",
+                "clock",
+                "crossfade",
+                "trim",
+                "ducking",
             ],
         )?;
 
@@ -119,7 +429,7 @@ impl Definitions {
                     .as_str()
                     .ok_or_else(|| anyhow::anyhow!("includes must be strings"))?;
                 let inc = normalize(&prefix.join(inc));
-                new.merge(Definitions::load_one(&inc)?);
+                new.merge(Definitions::load_one(&inc, problems)?);
             }
         }
 
@@ -128,21 +438,43 @@ impl Definitions {
             new.name = Some(name.to_owned());
         }
 
+        // read the crossfade time
+        if let Some(crossfade) = Self::get_str(data, "crossfade")? {
+            new.crossfade = Some(Self::parse_time(crossfade)?);
+        }
+
+        // read the silence trimming threshold
+        if let Some(trim) = Self::get_str(data, "trim")? {
+            new.trim = Some(
+                trim.parse()
+                    .map_err(|_| anyhow::anyhow!("bad trim threshold: {:?}", trim))?,
+            );
+        }
+
+        // read the voiceover ducking
+        if !data["ducking"].is_badvalue() {
+            new.ducking = Some(Self::parse_ducking(&data["ducking"])?);
+        }
+
         // read in simple path lists
-        new.solo.extend(Self::get_path_vec(data, "solo", &prefix)?);
+        new.solo
+            .extend(Self::get_path_vec(data, "solo", &prefix, problems)?);
         new.general
-            .extend(Self::get_path_vec(data, "general", &prefix)?);
+            .extend(Self::get_path_vec(data, "general", &prefix, problems)?);
         new.to_ad
-            .extend(Self::get_path_vec(data, "to-ad", &prefix)?);
+            .extend(Self::get_path_vec(data, "to-ad", &prefix, problems)?);
         new.to_news
-            .extend(Self::get_path_vec(data, "to-news", &prefix)?);
+            .extend(Self::get_path_vec(data, "to-news", &prefix, problems)?);
         new.time_evening
-            .extend(Self::get_path_vec(data, "time-evening", &prefix)?);
+            .extend(Self::get_path_vec(data, "time-evening", &prefix, problems)?);
         new.time_morning
-            .extend(Self::get_path_vec(data, "time-morning", &prefix)?);
-        new.id.extend(Self::get_path_vec(data, "id", &prefix)?);
-        new.ad.extend(Self::get_path_vec(data, "ad", &prefix)?);
-        new.news.extend(Self::get_path_vec(data, "news", &prefix)?);
+            .extend(Self::get_path_vec(data, "time-morning", &prefix, problems)?);
+        new.id
+            .extend(Self::get_path_vec(data, "id", &prefix, problems)?);
+        new.ad
+            .extend(Self::get_path_vec(data, "ad", &prefix, problems)?);
+        new.news
+            .extend(Self::get_path_vec(data, "news", &prefix, problems)?);
 
         // read intros
         if let Some(intros) = Self::get_vec(data, "intro")? {
@@ -151,7 +483,13 @@ impl Definitions {
                 Self::check_keys(intro, &["path", "title", "artist", "album"])?;
                 let path = Self::get_str(intro, "path")?
                     .ok_or_else(|| anyhow::anyhow!("song requires path"))?;
-                let path = Self::verify_media(&prefix.join(path))?;
+                let path = match Self::verify_media(&prefix.join(path)) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        problems.push(e);
+                        continue;
+                    }
+                };
                 let title = Self::get_str(intro, "title")?
                     .ok_or_else(|| anyhow::anyhow!("song requires title"))?
                     .to_owned();
@@ -177,7 +515,13 @@ impl Definitions {
                 Self::check_keys(song, &["path", "title", "artist", "album", "pre", "post"])?;
                 let path = Self::get_str(song, "path")?
                     .ok_or_else(|| anyhow::anyhow!("song requires path"))?;
-                let path = Self::verify_media(&prefix.join(path))?;
+                let path = match Self::verify_media(&prefix.join(path)) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        problems.push(e);
+                        continue;
+                    }
+                };
                 let title = Self::get_str(song, "title")?
                     .ok_or_else(|| anyhow::anyhow!("song requires title"))?
                     .to_owned();
@@ -186,9 +530,11 @@ impl Definitions {
                     .to_owned();
                 let album = Self::get_str(song, "album")?.map(|s| s.to_owned());
                 let pre = Self::get_str(song, "pre")?
-                    .ok_or_else(|| anyhow::anyhow!("song requires pre"))?;
+                    .map(Self::parse_time)
+                    .transpose()?;
                 let post = Self::get_str(song, "post")?
-                    .ok_or_else(|| anyhow::anyhow!("song requires post"))?;
+                    .map(Self::parse_time)
+                    .transpose()?;
                 new.music.push(Song {
                     path,
                     metadata: Metadata {
@@ -196,12 +542,48 @@ impl Definitions {
                         artist,
                         album,
                     },
-                    pre: Self::parse_time(pre)?,
-                    post: Self::parse_time(post)?,
+                    pre,
+                    post,
                 })
             }
         }
 
+        // read the programming clock
+        if let Some(segments) = Self::get_vec(data, "clock")? {
+            let mut clock = Vec::with_capacity(segments.len());
+            for segment in segments.iter() {
+                Self::check_keys(segment, &["type", "count", "chance"])?;
+                let kind = Self::get_str(segment, "type")?
+                    .ok_or_else(|| anyhow::anyhow!("clock segment requires type"))?;
+                let count = Self::get_str(segment, "count")?
+                    .map(|c| {
+                        c.parse::<u32>()
+                            .map_err(|_| anyhow::anyhow!("bad clock segment count: {:?}", c))
+                    })
+                    .transpose()?
+                    .unwrap_or(1);
+                let chance = Self::get_str(segment, "chance")?
+                    .map(|c| {
+                        c.parse::<f32>()
+                            .map_err(|_| anyhow::anyhow!("bad clock segment chance: {:?}", c))
+                    })
+                    .transpose()?
+                    .unwrap_or(1.0);
+                if !(0.0..=1.0).contains(&chance) {
+                    anyhow::bail!("clock segment chance must be between 0 and 1: {:?}", chance);
+                }
+                clock.push(ClockSegment {
+                    kind: SegmentKind::parse(kind)?,
+                    count,
+                    chance,
+                });
+            }
+            if clock.is_empty() {
+                anyhow::bail!("clock must have at least one segment");
+            }
+            new.clock = Some(clock);
+        }
+
         Ok(new)
     }
 
@@ -209,6 +591,18 @@ impl Definitions {
         if self.name.is_none() {
             self.name = other.name;
         }
+        if self.clock.is_none() {
+            self.clock = other.clock;
+        }
+        if self.crossfade.is_none() {
+            self.crossfade = other.crossfade;
+        }
+        if self.trim.is_none() {
+            self.trim = other.trim;
+        }
+        if self.ducking.is_none() {
+            self.ducking = other.ducking;
+        }
         self.solo.extend(other.solo);
         self.general.extend(other.general);
         self.to_ad.extend(other.to_ad);
@@ -224,17 +618,66 @@ impl Definitions {
 
     pub fn verify(&self) -> anyhow::Result<()> {
         // make sure each intro matches a song
-        'intro: for intro in self.intro.iter() {
-            for song in self.music.iter() {
-                if Self::meta_match(&intro.metadata, &song.metadata) {
-                    continue 'intro;
-                }
-            }
+        if let Some(intro) = self.unmatched_intros().next() {
             anyhow::bail!("intro does not match any song: {:?}", intro.path);
         }
         Ok(())
     }
 
+    fn unmatched_intros(&self) -> impl Iterator<Item = &Intro> {
+        self.intro.iter().filter(move |intro| {
+            !self
+                .music
+                .iter()
+                .any(|song| Self::meta_match(&intro.metadata, &song.metadata))
+        })
+    }
+
+    // every media file these definitions could play
+    pub fn all_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.solo
+            .iter()
+            .chain(self.general.iter())
+            .chain(self.to_ad.iter())
+            .chain(self.to_news.iter())
+            .chain(self.time_evening.iter())
+            .chain(self.time_morning.iter())
+            .chain(self.id.iter())
+            .chain(self.ad.iter())
+            .chain(self.news.iter())
+            .chain(self.intro.iter().map(|i| &i.path))
+            .chain(self.music.iter().map(|s| &s.path))
+    }
+
+    pub fn has_segment(&self, kind: SegmentKind) -> bool {
+        match kind {
+            SegmentKind::Music => !self.music.is_empty(),
+            SegmentKind::Ad => !self.ad.is_empty(),
+            SegmentKind::News => !self.news.is_empty(),
+            SegmentKind::Id => !self.id.is_empty(),
+            SegmentKind::Mono => !self.solo.is_empty(),
+            SegmentKind::Zone => false,
+        }
+    }
+
+    // find a song by "artist - title" or title, or failing that,
+    // the first song with the query somewhere in either
+    pub fn find_song(&self, query: &str) -> Option<&Song> {
+        let query = query.to_lowercase();
+        let names = |s: &Song| {
+            let title = s.metadata.title.to_lowercase();
+            let full = format!("{} - {}", s.metadata.artist.to_lowercase(), title);
+            (full, title)
+        };
+        self.music
+            .iter()
+            .find(|s| {
+                let (full, title) = names(s);
+                full == query || title == query
+            })
+            .or_else(|| self.music.iter().find(|s| names(s).0.contains(&query)))
+    }
+
     fn meta_match(a: &Metadata, b: &Metadata) -> bool {
         if let Some(ref aa) = a.album {
             if let Some(ref ab) = b.album {
@@ -273,14 +716,22 @@ impl Definitions {
         v.ok_or_else(|| anyhow::anyhow!("bad value for {:?}, expected list", k))
     }
 
-    fn get_path_vec(data: &StrictYaml, k: &str, prefix: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    fn get_path_vec(
+        data: &StrictYaml,
+        k: &str,
+        prefix: &Path,
+        problems: &mut Vec<anyhow::Error>,
+    ) -> anyhow::Result<Vec<PathBuf>> {
         if let Some(paths) = Self::get_vec(data, k)? {
             let mut ret = Vec::with_capacity(paths.len());
             for p in paths.iter() {
                 let p = p
                     .as_str()
                     .ok_or_else(|| anyhow::anyhow!("bad path in {:?}, expected string", k))?;
-                ret.push(Self::verify_media(&prefix.join(Path::new(p)))?);
+                match Self::verify_media(&prefix.join(Path::new(p))) {
+                    Ok(path) => ret.push(path),
+                    Err(e) => problems.push(e),
+                }
             }
             Ok(ret)
         } else {
@@ -306,7 +757,31 @@ impl Definitions {
         anyhow::bail!("file does not exist: {:?} (tried: {:?})", path, exts);
     }
 
-    fn parse_time(time: &str) -> anyhow::Result<f32> {
+    // anything left out is the default
+    fn parse_ducking(data: &StrictYaml) -> anyhow::Result<Ducking> {
+        Self::check_keys(data, &["threshold", "depth", "attack", "release"])?;
+        let mut ducking = Ducking::default();
+        for (k, v) in [
+            ("threshold", &mut ducking.threshold),
+            ("depth", &mut ducking.depth),
+        ] {
+            if let Some(s) = Self::get_str(data, k)? {
+                *v = s
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("bad ducking {}: {:?}", k, s))?;
+            }
+        }
+        if let Some(attack) = Self::get_str(data, "attack")? {
+            ducking.attack = Self::parse_time(attack)?;
+        }
+        if let Some(release) = Self::get_str(data, "release")? {
+            ducking.release = Self::parse_time(release)?;
+        }
+        Ok(ducking)
+    }
+
+    // seconds, or MM:SS, or HH:MM:SS
+    pub fn parse_time(time: &str) -> anyhow::Result<f32> {
         let mut r = 0.0;
         for part in time.split(":") {
             r *= 60.0;
@@ -0,0 +1,141 @@
+use flacenc::bitsink::ByteSink;
+use flacenc::component::{BitRepr, Stream, StreamInfo};
+use flacenc::error::{Verified, Verify};
+use flacenc::source::{Context, Fill, FrameBuf};
+
+// in frames
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: usize = 16;
+
+pub struct Flac {
+    samplerate: u32,
+    channels: u16,
+    config: Verified<flacenc::config::Encoder>,
+    info: StreamInfo,
+    framebuf: FrameBuf,
+    context: Context,
+    // interleaved samples waiting for a full block
+    pending: Vec<i32>,
+    header: Vec<u8>,
+    finished: bool,
+    out: Vec<u8>,
+}
+
+impl Flac {
+    pub fn new(samplerate: u32, channels: u16) -> anyhow::Result<Flac> {
+        let config = flacenc::config::Encoder::default()
+            .into_verified()
+            .map_err(|(_, e)| anyhow::anyhow!("could not create flac encoder: {}", e))?;
+        let mut info = StreamInfo::new(samplerate as usize, channels as usize, BITS_PER_SAMPLE)?;
+        info.set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)?;
+
+        Ok(Flac {
+            samplerate,
+            channels,
+            config,
+            header: Self::make_header(&info)?,
+            info,
+            framebuf: FrameBuf::with_size(channels as usize, BLOCK_SIZE)?,
+            context: Context::new(BITS_PER_SAMPLE, channels as usize),
+            pending: Vec::new(),
+            finished: false,
+            out: Vec::new(),
+        })
+    }
+
+    // the stream marker and metadata, with no frames
+    fn make_header(info: &StreamInfo) -> anyhow::Result<Vec<u8>> {
+        let mut sink = ByteSink::new();
+        Stream::with_stream_info(info.clone())
+            .write(&mut sink)
+            .map_err(|e| anyhow::anyhow!("flac encoding error: {}", e))?;
+        Ok(sink.into_inner())
+    }
+
+    fn encode_block(&mut self, samples: usize) -> anyhow::Result<()> {
+        (&mut self.framebuf, &mut self.context)
+            .fill_interleaved(&self.pending[..samples])
+            .map_err(|e| anyhow::anyhow!("flac encoding error: {}", e))?;
+        let number = self
+            .context
+            .current_frame_number()
+            .ok_or_else(|| anyhow::anyhow!("flac encoding error"))?;
+        let frame =
+            flacenc::encode_fixed_size_frame(&self.config, &self.framebuf, number, &self.info)
+                .map_err(|e| anyhow::anyhow!("flac encoding error: {}", e))?;
+        self.info.update_frame_info(&frame);
+
+        let mut sink = ByteSink::new();
+        frame
+            .write(&mut sink)
+            .map_err(|e| anyhow::anyhow!("flac encoding error: {}", e))?;
+        self.out.extend_from_slice(sink.as_slice());
+        self.pending.drain(..samples);
+        Ok(())
+    }
+}
+
+impl super::Encoder for Flac {
+    fn samplerate(&self) -> f32 {
+        self.samplerate as f32
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn format(&self) -> super::Format {
+        super::Format::Flac
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
+        if self.finished {
+            anyhow::bail!("flac encoder already finished");
+        }
+        self.pending.extend(buffer.iter().map(|v| {
+            let sample: i16 = cpal::Sample::from(v);
+            sample as i32
+        }));
+
+        self.out.clear();
+        let block = BLOCK_SIZE * self.channels as usize;
+        while self.pending.len() >= block {
+            self.encode_block(block)?;
+        }
+        Ok(&self.out)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        self.out.clear();
+        if self.finished {
+            return Ok(&self.out);
+        }
+        self.finished = true;
+
+        // the last block is allowed to be short
+        if !self.pending.is_empty() {
+            self.encode_block(self.pending.len())?;
+        }
+
+        // now we know enough to fill out the stream info. the short last
+        // block doesn't count against the block size, or decoders won't
+        // see a fixed block size stream
+        self.info.set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)?;
+        self.info.set_md5_digest(&self.context.md5_digest());
+        self.info.set_total_samples(self.context.total_samples());
+        self.header = Self::make_header(&self.info)?;
+        Ok(&self.out)
+    }
+
+    fn rewrite_header(&self) -> Option<&[u8]> {
+        if self.finished {
+            Some(&self.header)
+        } else {
+            None
+        }
+    }
+}
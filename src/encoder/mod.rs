@@ -1,17 +1,133 @@
+mod flac;
 mod mp3;
+mod opus;
+mod vorbis;
+mod wav;
 
+pub use self::opus::Opus;
+pub use flac::Flac;
 pub use mp3::Mp3;
+pub use vorbis::Vorbis;
+pub use wav::Wav;
 
 #[derive(Clone, Debug)]
 pub enum Format {
     Mp3,
+    Vorbis,
+    Opus,
+    Wav,
+    Flac,
     Other(String),
 }
 
+impl Format {
+    // accepts format names as well as file extensions
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_ref() {
+            "mp3" => Format::Mp3,
+            "ogg" | "oga" | "vorbis" => Format::Vorbis,
+            "opus" => Format::Opus,
+            "wav" | "wave" => Format::Wav,
+            "flac" => Format::Flac,
+            _ => return None,
+        })
+    }
+
+    pub fn extension(&self) -> &str {
+        match self {
+            Format::Mp3 => "mp3",
+            Format::Vorbis => "ogg",
+            Format::Opus => "opus",
+            Format::Wav => "wav",
+            Format::Flac => "flac",
+            Format::Other(ref ext) => ext,
+        }
+    }
+
+    pub fn content_type(&self) -> &str {
+        match self {
+            Format::Mp3 => "audio/mpeg",
+            Format::Vorbis | Format::Opus => "audio/ogg",
+            Format::Wav => "audio/wav",
+            Format::Flac => "audio/flac",
+            Format::Other(_) => "application/octet-stream",
+        }
+    }
+
+    // an encoder for this format, with default settings
+    pub fn encoder(&self, samplerate: u32, channels: u16) -> anyhow::Result<Box<dyn Encoder>> {
+        Ok(match self {
+            Format::Mp3 => Box::new(Mp3::new(samplerate, channels, None, None)?),
+            Format::Vorbis => Box::new(Vorbis::new(samplerate, channels, None)?),
+            Format::Opus => Box::new(Opus::new(samplerate, channels, None)?),
+            Format::Wav => Box::new(Wav::new(samplerate, channels)?),
+            Format::Flac => Box::new(Flac::new(samplerate, channels)?),
+            Format::Other(ref name) => anyhow::bail!("no encoder for format {:?}", name),
+        })
+    }
+}
+
 pub trait Encoder {
     fn samplerate(&self) -> f32;
     fn channels(&self) -> u16;
     fn format(&self) -> Format;
 
+    // stream headers that must come before any encoded data.
+    // these are not included in the output of encode.
+    fn header(&self) -> &[u8] {
+        &[]
+    }
+
     fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]>;
+
+    // seconds of audio taken in but not yet encoded
+    fn latency(&self) -> f32 {
+        0.0
+    }
+
+    // flush out anything still buffered at the end of the stream.
+    // nothing may be encoded afterwards.
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        Ok(&[])
+    }
+
+    // once finished, replacement bytes for the very start of the
+    // stream, for outputs that can go back and rewrite it
+    fn rewrite_header(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Encoder for Box<dyn Encoder> {
+    fn samplerate(&self) -> f32 {
+        (**self).samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        (**self).channels()
+    }
+
+    fn format(&self) -> Format {
+        (**self).format()
+    }
+
+    fn header(&self) -> &[u8] {
+        (**self).header()
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
+        (**self).encode(buffer)
+    }
+
+    fn latency(&self) -> f32 {
+        (**self).latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        (**self).finish()
+    }
+
+    fn rewrite_header(&self) -> Option<&[u8]> {
+        (**self).rewrite_header()
+    }
 }
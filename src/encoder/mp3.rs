@@ -1,34 +1,79 @@
+use mp3lame_encoder::{Bitrate, DualPcm, FlushGap, MonoPcm, Quality};
+
 pub struct Mp3 {
     samplerate: u32,
-    lame: lame::Lame,
+    channels: u16,
+    lame: mp3lame_encoder::Encoder,
     left: Vec<i16>,
     right: Vec<i16>,
     out: Vec<u8>,
+    // the final LAME tag, to replace the placeholder at the start
+    lametag: Vec<u8>,
 }
 
 impl Mp3 {
-    pub fn new(samplerate: u32, kbitrate: Option<i32>, quality: Option<u8>) -> anyhow::Result<Mp3> {
-        let mut lame =
-            lame::Lame::new().ok_or_else(|| anyhow::anyhow!("out of memory in mp3 encoder"))?;
+    pub fn new(
+        samplerate: u32,
+        channels: u16,
+        kbitrate: Option<i32>,
+        quality: Option<u8>,
+    ) -> anyhow::Result<Mp3> {
+        if channels != 1 && channels != 2 {
+            anyhow::bail!("mp3 only supports mono or stereo");
+        }
+        let mut lame = mp3lame_encoder::Builder::new()
+            .ok_or_else(|| anyhow::anyhow!("out of memory in mp3 encoder"))?;
         lame.set_sample_rate(samplerate)
             .map_err(|_| anyhow::anyhow!("could not create mp3 encoder"))?;
-        lame.set_channels(2)
+        lame.set_num_channels(channels as u8)
             .map_err(|_| anyhow::anyhow!("could not create mp3 encoder"))?;
-        lame.set_quality(quality.unwrap_or(5))
+        lame.set_quality(Self::quality(quality.unwrap_or(5)))
             .map_err(|_| anyhow::anyhow!("could not create mp3 encoder"))?;
-        lame.set_kilobitrate(kbitrate.unwrap_or(300))
+        lame.set_brate(Self::bitrate(kbitrate.unwrap_or(300)))
             .map_err(|_| anyhow::anyhow!("could not create mp3 encoder"))?;
-        lame.init_params()
+        let lame = lame
+            .build()
             .map_err(|_| anyhow::anyhow!("could not create mp3 encoder"))?;
 
         Ok(Mp3 {
             samplerate,
+            channels,
             lame,
             left: Vec::new(),
             right: Vec::new(),
             out: Vec::new(),
+            lametag: Vec::new(),
         })
     }
+
+    // the closest bitrate mp3 supports
+    fn bitrate(kbitrate: i32) -> Bitrate {
+        use Bitrate::*;
+        let rates = [
+            Kbps8, Kbps16, Kbps24, Kbps32, Kbps40, Kbps48, Kbps64, Kbps80, Kbps96, Kbps112,
+            Kbps128, Kbps160, Kbps192, Kbps224, Kbps256, Kbps320,
+        ];
+        rates
+            .into_iter()
+            .min_by_key(|r| (*r as i32 - kbitrate).abs())
+            .unwrap()
+    }
+
+    fn quality(quality: u8) -> Quality {
+        use Quality::*;
+        match quality {
+            0 => Best,
+            1 => SecondBest,
+            2 => NearBest,
+            3 => VeryNice,
+            4 => Nice,
+            5 => Good,
+            6 => Decent,
+            7 => Ok,
+            8 => SecondWorst,
+            _ => Worst,
+        }
+    }
 }
 
 impl super::Encoder for Mp3 {
@@ -37,7 +82,7 @@ impl super::Encoder for Mp3 {
     }
 
     fn channels(&self) -> u16 {
-        2
+        self.channels
     }
 
     fn format(&self) -> super::Format {
@@ -45,26 +90,54 @@ impl super::Encoder for Mp3 {
     }
 
     fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
-        let samples = (buffer.len() + 1) / 2;
+        let channels = self.channels as usize;
+        let samples = buffer.len().div_ceil(channels);
         self.left.resize(samples, 0);
         self.right.resize(samples, 0);
 
-        // from lame.h, worst case
-        let mp3size = ((samples * 5 + 3) / 4) + 7200;
-        self.out.resize(mp3size, 0);
-
         for (i, v) in buffer.iter().enumerate() {
-            if i % 2 > 0 {
-                self.right[i / 2] = cpal::Sample::from(v);
+            if i % channels > 0 {
+                self.right[i / channels] = cpal::Sample::from(v);
             } else {
-                self.left[i / 2] = cpal::Sample::from(v);
+                self.left[i / channels] = cpal::Sample::from(v);
             }
         }
 
-        let amt = self
-            .lame
-            .encode(&self.left, &self.right, &mut self.out)
+        self.out.clear();
+        self.out
+            .reserve(mp3lame_encoder::max_required_buffer_size(samples));
+        let encoded = if channels == 1 {
+            self.lame.encode_to_vec(MonoPcm(&self.left), &mut self.out)
+        } else {
+            let input = DualPcm {
+                left: &self.left,
+                right: &self.right,
+            };
+            self.lame.encode_to_vec(input, &mut self.out)
+        };
+        encoded.map_err(|_| anyhow::anyhow!("mp3 encoding error"))?;
+        Ok(&self.out)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        self.out.clear();
+        self.out
+            .reserve(mp3lame_encoder::max_required_buffer_size(0));
+        self.lame
+            .flush_to_vec::<FlushGap>(&mut self.out)
             .map_err(|_| anyhow::anyhow!("mp3 encoding error"))?;
-        Ok(&self.out[..amt])
+
+        self.lametag.clear();
+        self.lametag.reserve(self.lame.lame_tag_size());
+        self.lame.lame_tag_encode_to_vec(&mut self.lametag);
+        Ok(&self.out)
+    }
+
+    fn rewrite_header(&self) -> Option<&[u8]> {
+        if self.lametag.is_empty() {
+            None
+        } else {
+            Some(&self.lametag)
+        }
     }
 }
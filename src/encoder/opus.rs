@@ -0,0 +1,221 @@
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+// opus frames are 20ms long
+const FRAMES_PER_SECOND: u32 = 50;
+// largest packet recommended by the opus docs
+const MAX_PACKET: usize = 4000;
+
+pub struct Opus {
+    samplerate: u32,
+    channels: u16,
+    opus: opus::Encoder,
+    writer: PacketWriter<'static, Vec<u8>>,
+    serial: u32,
+    header: Vec<u8>,
+    // encoder delay, in input samples
+    lookahead: usize,
+    finished: bool,
+    // interleaved samples waiting for a full frame
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+    // in 48kHz samples, as ogg opus requires
+    granule: u64,
+    out: Vec<u8>,
+}
+
+impl Opus {
+    pub fn new(samplerate: u32, channels: u16, kbitrate: Option<i32>) -> anyhow::Result<Opus> {
+        let layout = match channels {
+            1 => opus::Channels::Mono,
+            2 => opus::Channels::Stereo,
+            _ => anyhow::bail!("opus only supports mono or stereo"),
+        };
+        let mut opus = opus::Encoder::new(samplerate, layout, opus::Application::Audio)?;
+        opus.set_bitrate(opus::Bitrate::Bits(kbitrate.unwrap_or(128) * 1000))?;
+        let lookahead = opus.get_lookahead()? as usize;
+        let preskip = lookahead as u64 * 48000 / samplerate as u64;
+
+        let serial = rand::random();
+        let mut writer = PacketWriter::new(Vec::new());
+
+        // identification header, see RFC 7845
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&(preskip as u16).to_le_bytes());
+        head.extend_from_slice(&samplerate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        writer.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        // comment header, with no comments
+        let vendor = concat!("sprunk ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_packet(tags, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let header = std::mem::take(writer.inner_mut());
+
+        Ok(Opus {
+            samplerate,
+            channels,
+            opus,
+            writer,
+            serial,
+            header,
+            lookahead,
+            finished: false,
+            pending: Vec::new(),
+            packet: vec![0; MAX_PACKET],
+            granule: 0,
+            out: Vec::new(),
+        })
+    }
+}
+
+impl super::Encoder for Opus {
+    fn samplerate(&self) -> f32 {
+        self.samplerate as f32
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn format(&self) -> super::Format {
+        super::Format::Opus
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
+        if self.finished {
+            anyhow::bail!("opus encoder already finished");
+        }
+        self.pending.extend_from_slice(buffer);
+        self.encode_pending(None)?;
+        self.out = std::mem::take(self.writer.inner_mut());
+        Ok(&self.out)
+    }
+
+    fn latency(&self) -> f32 {
+        let frames = self.pending.len() / self.channels as usize + self.lookahead;
+        frames as f32 / self.samplerate as f32
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        if !self.finished {
+            self.finished = true;
+
+            // the last page says where the real audio ends
+            let scale = 48000 / self.samplerate as u64;
+            let channels = self.channels as usize;
+            let end = self.granule + (self.pending.len() / channels) as u64 * scale;
+            let end = end + self.lookahead as u64 * scale;
+
+            // push the encoder delay out, and pad to a whole frame.
+            // always at least one, so there's a page to end the stream on.
+            let frame = (self.samplerate / FRAMES_PER_SECOND) as usize;
+            let frames = self.pending.len() / channels + self.lookahead;
+            let padded = frames.div_ceil(frame).max(1) * frame;
+            self.pending.resize(padded * channels, 0.0);
+            self.encode_pending(Some(end))?;
+        }
+
+        self.out = std::mem::take(self.writer.inner_mut());
+        Ok(&self.out)
+    }
+}
+
+impl Opus {
+    // encode all whole frames waiting, ending the stream at end if given
+    fn encode_pending(&mut self, end: Option<u64>) -> anyhow::Result<()> {
+        let frame = (self.samplerate / FRAMES_PER_SECOND) as usize;
+        let size = frame * self.channels as usize;
+        let chunks = self.pending.len() / size;
+        for i in 0..chunks {
+            let samples = &self.pending[i * size..(i + 1) * size];
+            let amt = self.opus.encode_float(samples, &mut self.packet)?;
+            self.granule += frame as u64 * 48000 / self.samplerate as u64;
+
+            // finish a page at the end of each call, so output is always whole pages
+            let last = i + 1 == chunks;
+            let (info, granule) = match end {
+                Some(end) if last => (PacketWriteEndInfo::EndStream, end),
+                _ if last => (PacketWriteEndInfo::EndPage, self.granule),
+                _ => (PacketWriteEndInfo::NormalPacket, self.granule),
+            };
+            self.writer
+                .write_packet(self.packet[..amt].to_vec(), self.serial, info, granule)?;
+        }
+        self.pending.drain(..chunks * size);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Opus;
+    use crate::Encoder;
+
+    // every packet in a stream, with its page's granule and end of stream flag
+    fn packets(data: Vec<u8>) -> Vec<(u64, bool)> {
+        let mut reader = ogg::reading::PacketReader::new(std::io::Cursor::new(data));
+        let mut packets = vec![];
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push((packet.absgp_page(), packet.last_in_stream()));
+        }
+        packets
+    }
+
+    fn encode(samplerate: u32, frames: usize) -> (Vec<u8>, u64) {
+        let mut opus = Opus::new(samplerate, 2, None).unwrap();
+        let mut data = opus.header().to_vec();
+        let input: Vec<f32> = (0..frames * 2)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect();
+        // pieces that don't line up with opus frames
+        for chunk in input.chunks(1234) {
+            data.extend_from_slice(opus.encode(chunk).unwrap());
+        }
+        data.extend_from_slice(opus.finish().unwrap());
+        assert!(opus.finish().unwrap().is_empty());
+        let preskip = u16::from_le_bytes([data[28 + 10], data[28 + 11]]) as u64;
+        (data, preskip)
+    }
+
+    #[test]
+    fn granules_end_on_the_last_sample() {
+        for (samplerate, frames) in [(48000, 48000), (24000, 12345), (48000, 0)] {
+            let (data, preskip) = encode(samplerate, frames);
+            let packets = packets(data);
+            // two headers, then audio
+            assert!(packets.len() > 2);
+            assert_eq!(packets[0], (0, false));
+            assert_eq!(packets[1], (0, false));
+            for pair in packets[2..].windows(2) {
+                assert!(pair[0].0 <= pair[1].0);
+                assert!(!pair[0].1);
+            }
+            let scale = 48000 / samplerate as u64;
+            assert_eq!(
+                *packets.last().unwrap(),
+                (preskip + frames as u64 * scale, true)
+            );
+        }
+    }
+
+    #[test]
+    fn empty_stream_still_ends() {
+        // nothing to flush, not even encoder delay
+        let mut opus = Opus::new(48000, 2, None).unwrap();
+        opus.lookahead = 0;
+        let mut data = opus.header().to_vec();
+        data.extend_from_slice(opus.finish().unwrap());
+        assert_eq!(packets(data).last(), Some(&(0, true)));
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::rc::Rc;
+
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoder, VorbisEncoderBuilder};
+
+// the vorbis encoder owns its writer, so we share a buffer with it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Vorbis {
+    samplerate: u32,
+    channels: u16,
+    // taken when finished
+    encoder: Option<VorbisEncoder<SharedBuffer>>,
+    buffer: SharedBuffer,
+    header: Vec<u8>,
+    // one buffer per channel
+    planar: Vec<Vec<f32>>,
+    out: Vec<u8>,
+}
+
+impl Vorbis {
+    pub fn new(samplerate: u32, channels: u16, quality: Option<f32>) -> anyhow::Result<Vorbis> {
+        let buffer = SharedBuffer::default();
+        let mut builder = VorbisEncoderBuilder::new(
+            NonZeroU32::new(samplerate)
+                .ok_or_else(|| anyhow::anyhow!("could not create vorbis encoder"))?,
+            u8::try_from(channels)
+                .ok()
+                .and_then(NonZeroU8::new)
+                .ok_or_else(|| anyhow::anyhow!("could not create vorbis encoder"))?,
+            buffer.clone(),
+        )?;
+        builder.bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr {
+            target_quality: quality.unwrap_or(0.6),
+        });
+        let encoder = builder.build()?;
+
+        // building the encoder writes out the stream headers
+        let header = buffer.0.take();
+
+        Ok(Vorbis {
+            samplerate,
+            channels,
+            encoder: Some(encoder),
+            buffer,
+            header,
+            planar: vec![Vec::new(); channels as usize],
+            out: Vec::new(),
+        })
+    }
+}
+
+impl super::Encoder for Vorbis {
+    fn samplerate(&self) -> f32 {
+        self.samplerate as f32
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn format(&self) -> super::Format {
+        super::Format::Vorbis
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
+        let channels = self.channels as usize;
+        for (c, planar) in self.planar.iter_mut().enumerate() {
+            planar.clear();
+            planar.extend(buffer.iter().skip(c).step_by(channels));
+        }
+
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("vorbis encoder already finished"))?;
+        if !self.planar[0].is_empty() {
+            encoder.encode_audio_block(&self.planar)?;
+        }
+
+        self.out = self.buffer.0.take();
+        Ok(&self.out)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?;
+        }
+        self.out = self.buffer.0.take();
+        Ok(&self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Vorbis;
+    use crate::Encoder;
+
+    #[test]
+    fn granules_end_on_the_last_sample() {
+        let frames = 44100 + 123;
+        let mut vorbis = Vorbis::new(44100, 2, None).unwrap();
+        let mut data = vorbis.header().to_vec();
+        let input: Vec<f32> = (0..frames * 2)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect();
+        for chunk in input.chunks(1234) {
+            data.extend_from_slice(vorbis.encode(chunk).unwrap());
+        }
+        data.extend_from_slice(vorbis.finish().unwrap());
+
+        let mut reader = ogg::reading::PacketReader::new(std::io::Cursor::new(data));
+        let mut last = (0, false);
+        while let Some(packet) = reader.read_packet().unwrap() {
+            assert!(!last.1, "packets after the end of the stream");
+            assert!(packet.absgp_page() >= last.0);
+            last = (packet.absgp_page(), packet.last_in_stream());
+        }
+        assert_eq!(last, (frames as u64, true));
+    }
+}
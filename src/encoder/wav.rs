@@ -0,0 +1,87 @@
+// 16-bit PCM in a RIFF container
+pub struct Wav {
+    samplerate: u32,
+    channels: u16,
+    // bytes of sample data written so far
+    written: u64,
+    header: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Wav {
+    pub fn new(samplerate: u32, channels: u16) -> anyhow::Result<Wav> {
+        if channels == 0 {
+            anyhow::bail!("could not create wav encoder");
+        }
+        Ok(Wav {
+            samplerate,
+            channels,
+            written: 0,
+            // sizes aren't known yet, so claim to be as long as possible
+            header: Self::make_header(samplerate, channels, None),
+            out: Vec::new(),
+        })
+    }
+
+    fn make_header(samplerate: u32, channels: u16, data_size: Option<u32>) -> Vec<u8> {
+        let data_size = data_size.unwrap_or(u32::MAX - 36);
+        let block_align = channels * 2;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(data_size + 36).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // integer PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&samplerate.to_le_bytes());
+        header.extend_from_slice(&(samplerate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        header
+    }
+}
+
+impl super::Encoder for Wav {
+    fn samplerate(&self) -> f32 {
+        self.samplerate as f32
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn format(&self) -> super::Format {
+        super::Format::Wav
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn encode(&mut self, buffer: &[f32]) -> anyhow::Result<&[u8]> {
+        self.out.clear();
+        for v in buffer {
+            let sample: i16 = cpal::Sample::from(v);
+            self.out.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.written += self.out.len() as u64;
+        Ok(&self.out)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<&[u8]> {
+        // wav can't describe more than 4GB, so leave it at the maximum
+        if let Ok(size) = u32::try_from(self.written) {
+            if size <= u32::MAX - 36 {
+                self.header = Self::make_header(self.samplerate, self.channels, Some(size));
+            }
+        }
+        Ok(&[])
+    }
+
+    fn rewrite_header(&self) -> Option<&[u8]> {
+        Some(&self.header)
+    }
+}
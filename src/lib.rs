@@ -1,25 +1,37 @@
+mod analysis_cache;
+pub mod control;
+mod cues;
 mod definitions;
 pub mod encoder;
 mod manager;
+mod master;
 mod normalize;
+pub mod playlist;
+pub mod polyphase;
 mod radio;
 mod radio_index;
 mod random_mixer;
+#[cfg(feature = "libsamplerate")]
 pub mod samplerate;
 mod scheduler;
+mod segment;
 mod server;
 pub mod sink;
 mod soft_scheduler;
 pub mod source;
 pub mod wow;
 
-pub use definitions::{Definitions, Intro, Metadata, Song};
+pub use analysis_cache::AnalysisCache;
+pub use cues::{detect_cues, detect_file_cues, CueDetector, Cues};
+pub use definitions::{ClockSegment, Definitions, Intro, Metadata, SegmentKind, Song};
 pub use encoder::Encoder;
 pub use manager::Manager;
+pub use master::{AgcSettings, LimiterSettings, Master, MasterSettings};
 pub use radio::Radio;
-pub use radio_index::{Output, RadioIndex, RadioInfo};
+pub use radio_index::{ControlAuth, Output, RadioIndex, RadioInfo, RenderLength};
 pub use random_mixer::RandomMixer;
 pub use scheduler::{Scheduler, SchedulerSource, SchedulerTask, Time};
+pub use segment::Segment;
 pub use server::server_run;
 pub use sink::Sink;
 pub use soft_scheduler::SoftScheduler;
@@ -13,9 +13,30 @@ async fn run() -> anyhow::Result<()> {
         sprunk =>
             (@subcommand play =>
              (@arg OUTPUT: -o --output +takes_value "set output")
+             (@arg SAMPLERATE: -r --samplerate +takes_value "set output sample rate")
+             (@arg CHANNELS: -c --channels +takes_value "set output channel count")
              (@arg RADIOYAML: +required "radio definitions list")
              (@arg MOUNT: +required "radio mount point")
             )
+            (@subcommand render =>
+             (@arg DURATION: -d --duration +takes_value "how long to render, as [[HH:]MM:]SS")
+             (@arg SEGMENTS: -n --segments +takes_value "how many segments to render")
+             (@arg SAMPLERATE: -r --samplerate +takes_value "set output sample rate")
+             (@arg CHANNELS: -c --channels +takes_value "set output channel count")
+             (@arg RADIOYAML: +required "radio definitions list")
+             (@arg MOUNT: +required "radio mount point")
+             (@arg OUTPUT: +required "output file")
+            )
+            (@subcommand check =>
+             (@arg RADIOYAML: +required "radio definitions list")
+            )
+            (@subcommand cues =>
+             (@arg ALL: -a --all "also guess for songs that have cue points")
+             (@arg RADIOYAML: +required "radio definitions list")
+            )
+            (@subcommand scan =>
+             (@arg RADIOYAML: +required "radio definitions list")
+            )
             (@subcommand serve =>
              (@arg BIND: -b --bind +takes_value "set server bind")
              (@arg RADIOYAML: +required "radio definitions list")
@@ -27,18 +48,120 @@ async fn run() -> anyhow::Result<()> {
     if let Some(matches) = matches.subcommand_matches("play") {
         let radioyaml = matches.value_of("RADIOYAML").unwrap();
         let mount = matches.value_of("MOUNT").unwrap();
+        let (samplerate, channels) = audio_settings(matches)?;
         let output = matches
             .value_of("OUTPUT")
             .map(|s| sprunk::Output::from_str(s))
             .transpose()?
-            .map(|s| s.to_sink(24000))
+            .map(|mut s| {
+                s.set_audio(samplerate, channels);
+                s.to_sink(24000)
+            })
             .transpose()?;
         let index = sprunk::RadioIndex::open(&radioyaml)?;
-        index.play(mount, output, false, |m| {
+        index.play(mount, output, false, None, |m| {
             println!("{}", m);
         })?;
     }
 
+    if let Some(matches) = matches.subcommand_matches("render") {
+        let radioyaml = matches.value_of("RADIOYAML").unwrap();
+        let mount = matches.value_of("MOUNT").unwrap();
+        let length = match (matches.value_of("DURATION"), matches.value_of("SEGMENTS")) {
+            (Some(d), None) => sprunk::RenderLength::Duration(sprunk::Definitions::parse_time(d)?),
+            (None, Some(n)) => sprunk::RenderLength::Segments(n.parse()?),
+            _ => anyhow::bail!("render needs one of --duration or --segments"),
+        };
+        let (samplerate, channels) = audio_settings(matches)?;
+        let mut output = sprunk::Output::from_str(matches.value_of("OUTPUT").unwrap())?;
+        output.set_audio(samplerate, channels);
+        let index = sprunk::RadioIndex::open(radioyaml)?;
+        index.render(mount, output.to_sink(24000)?, length, |m| {
+            println!("{}", m);
+        })?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let radioyaml = matches.value_of("RADIOYAML").unwrap();
+        let index = sprunk::RadioIndex::open(radioyaml)?;
+        let mut total = 0;
+        for (station, problems) in index.lint() {
+            for problem in problems.iter() {
+                println!("{}: {:#}", station, problem);
+            }
+            total += problems.len();
+        }
+        if total > 0 {
+            anyhow::bail!("found {} problems", total);
+        }
+        println!("no problems found");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("cues") {
+        use rayon::prelude::*;
+        let radioyaml = matches.value_of("RADIOYAML").unwrap();
+        let all = matches.is_present("ALL");
+        let index = sprunk::RadioIndex::open(radioyaml)?;
+        for (station, prefix, songs) in index.songs()? {
+            let songs: Vec<_> = songs
+                .into_iter()
+                .filter(|s| all || s.pre.is_none() || s.post.is_none())
+                .collect();
+            if songs.is_empty() {
+                continue;
+            }
+            let cues: Vec<_> = songs
+                .par_iter()
+                .map(|s| sprunk::detect_file_cues(&s.path))
+                .collect();
+
+            // suggested music entries, ready to paste in
+            println!("# {}", station);
+            println!("music:");
+            for (song, cues) in songs.iter().zip(cues) {
+                let cues = match cues {
+                    Ok(cues) => cues,
+                    Err(e) => {
+                        eprintln!("Error: {}: {:#}", song.path.display(), e);
+                        continue;
+                    }
+                };
+                let path = relative_to(&song.path.with_extension(""), &prefix);
+                println!("  - path: {}", quote(&path.to_string_lossy()));
+                println!("    title: {}", quote(&song.metadata.title));
+                println!("    artist: {}", quote(&song.metadata.artist));
+                if let Some(album) = &song.metadata.album {
+                    println!("    album: {}", quote(album));
+                }
+                println!("    pre: {}", format_time(song.pre.unwrap_or(cues.pre)));
+                println!("    post: {}", format_time(song.post.unwrap_or(cues.post)));
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("scan") {
+        let radioyaml = matches.value_of("RADIOYAML").unwrap();
+        let index = sprunk::RadioIndex::open(radioyaml)?;
+        if !index.analysis_cache().is_persistent() {
+            anyhow::bail!("set loudness-cache in {} to keep scan results", radioyaml);
+        }
+        let paths = index.media_paths()?;
+        let results = index.analysis_cache().scan(&paths, |path, r| match r {
+            Ok(lufs) => println!("{:.1} LUFS\t{}", lufs, path.display()),
+            Err(e) => eprintln!("Error: {}: {:#}", path.display(), e),
+        });
+        let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+        println!(
+            "scanned {} of {} files, {} failed",
+            results.len(),
+            paths.len(),
+            failed
+        );
+        if failed > 0 {
+            anyhow::bail!("could not measure {} files", failed);
+        }
+    }
+
     if let Some(matches) = matches.subcommand_matches("serve") {
         let radioyaml = matches.value_of("RADIOYAML").unwrap();
         let staticfiles = matches.value_of("STATICFILES").unwrap();
@@ -54,3 +177,51 @@ async fn run() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+// as M:SS.S, which definitions can read back
+fn format_time(seconds: f32) -> String {
+    let tenths = (seconds * 10.0).round() as u32;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+// a double-quoted yaml string, safe for any title
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// path as seen from base, going up with .. where needed
+fn relative_to(path: &std::path::Path, base: &std::path::Path) -> std::path::PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = std::path::PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+fn audio_settings(matches: &clap::ArgMatches) -> anyhow::Result<(Option<u32>, Option<u16>)> {
+    let samplerate = matches
+        .value_of("SAMPLERATE")
+        .map(|r| r.parse())
+        .transpose()?;
+    let channels = matches
+        .value_of("CHANNELS")
+        .map(|c| c.parse())
+        .transpose()?;
+    Ok((samplerate, channels))
+}
@@ -1,11 +1,21 @@
-use crate::{Scheduler, SchedulerSource, SchedulerTask, Sink, Source, Time};
+use crate::scheduler::Mark;
+use crate::{
+    Master, MasterSettings, Scheduler, SchedulerSource, SchedulerTask, Sink, Source, Time,
+};
 
 pub struct Manager<S, T> {
     sink: S,
     buffer: Vec<f32>,
     buffersize: u64,
     offset: u64,
+    // scheduler time taken from the source so far, in frames. this is
+    // what marks are for, so it doesn't count silence while paused.
+    played: u64,
+    // marks waiting for their audio to be heard
+    marks: Vec<(u64, Mark)>,
     source: SchedulerSource,
+    // processing between the source and the sink, if any
+    master: Option<Master>,
     task: SchedulerTask<anyhow::Result<T>>,
 }
 
@@ -24,12 +34,28 @@ where
             buffer: vec![0.0; buffersize * sink.channels() as usize],
             buffersize: buffersize as u64,
             offset: 0,
+            played: 0,
+            marks: Vec::new(),
             sink,
             source,
+            master: None,
             task: scheduler.run(f),
         }
     }
 
+    // process all audio with these settings before it reaches the sink
+    pub fn set_master(&mut self, settings: &MasterSettings) {
+        self.master = if settings.is_empty() {
+            None
+        } else {
+            Some(Master::new(
+                settings,
+                self.source.samplerate(),
+                self.source.channels(),
+            ))
+        };
+    }
+
     pub fn advance<Ti>(&mut self, frames: Ti) -> anyhow::Result<()>
     where
         Ti: Into<Time>,
@@ -37,34 +63,179 @@ where
         self.offset += frames.into().to_frames(self.source.samplerate());
         while self.offset > self.buffersize {
             // emit a chunk
-            let avail = self.source.force_fill(&mut self.buffer);
+            let avail = self.source.force_fill(&mut self.buffer)?;
+            self.report_errors();
             self.buffer[avail..].iter_mut().for_each(|v| *v = 0.0);
+            if let Some(master) = &mut self.master {
+                master.process(&mut self.buffer);
+            }
             self.sink.write(&self.buffer)?;
+            self.played = self.source.now().to_frames(self.source.samplerate());
+            self.release_marks(true);
 
             self.offset -= self.buffersize;
         }
         Ok(())
     }
 
-    pub fn skip<Ti>(&mut self, frames: Ti)
+    // write out exactly this much audio, as fast as we can,
+    // stopping early if the task finishes
+    pub fn render<Ti>(&mut self, length: Ti) -> anyhow::Result<()>
+    where
+        Ti: Into<Time>,
+    {
+        let channels = self.source.channels() as u64;
+        let mut frames = length.into().to_frames(self.source.samplerate());
+        while frames > 0 && !self.is_finished() {
+            let amt = frames.min(self.buffersize);
+            let buffer = &mut self.buffer[..(amt * channels) as usize];
+            let avail = self.source.force_fill(buffer)?;
+            buffer[avail..].iter_mut().for_each(|v| *v = 0.0);
+            if let Some(master) = &mut self.master {
+                master.process(buffer);
+            }
+            self.sink.write(buffer)?;
+            self.report_errors();
+            self.played = self.source.now().to_frames(self.source.samplerate());
+            self.release_marks(true);
+            frames -= amt;
+        }
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub fn skip<Ti>(&mut self, frames: Ti) -> anyhow::Result<()>
     where
         Ti: Into<Time>,
     {
         self.offset += frames.into().to_frames(self.source.samplerate());
         while self.offset > self.buffersize {
             // this *could* be done more efficiently, but this is fine
-            self.source.force_fill(&mut self.buffer);
+            self.source.force_fill(&mut self.buffer)?;
+            self.report_errors();
+            // nobody hears skipped audio, so don't wait on it
+            self.played = self.source.now().to_frames(self.source.samplerate());
+            self.release_marks(false);
             self.offset -= self.buffersize;
         }
+        Ok(())
     }
 
     pub fn advance_to_end(mut self) -> anyhow::Result<T> {
         loop {
-            let avail = self.source.fill(&mut self.buffer);
+            let avail = self.source.fill(&mut self.buffer)?;
+            self.report_errors();
             if avail == 0 {
+                self.flush_master()?;
+                self.sink.finish()?;
+                self.release_marks(false);
                 return self.source.resolve(self.task);
             }
+            if let Some(master) = &mut self.master {
+                master.process(&mut self.buffer[..avail]);
+            }
             self.sink.write(&self.buffer[..avail])?;
+            self.played = self.source.now().to_frames(self.source.samplerate());
+            self.release_marks(true);
         }
     }
+
+    // stop here, and flush out the sink
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.flush_master()?;
+        self.sink.finish()?;
+        self.release_marks(false);
+        Ok(())
+    }
+
+    // write out audio still held back by master processing
+    fn flush_master(&mut self) -> anyhow::Result<()> {
+        if let Some(master) = &mut self.master {
+            self.sink.write(&master.flush())?;
+        }
+        Ok(())
+    }
+
+    // run marks for audio that has made it out of the sink,
+    // allowing for sink latency if asked
+    fn release_marks(&mut self, latency: bool) {
+        self.marks.extend(self.source.take_marks());
+        let delay = if latency {
+            let master = self.master.as_ref().map(|m| m.latency()).unwrap_or(0.0);
+            let seconds = self.sink.latency() + master;
+            self.source.set_latency(seconds);
+            (seconds * self.source.samplerate()) as u64
+        } else {
+            0
+        };
+        let heard = self.played.saturating_sub(delay);
+        self.marks.sort_by_key(|m| m.0);
+        let due = self.marks.partition_point(|m| m.0 < heard);
+        for (_, mark) in self.marks.drain(..due) {
+            mark();
+        }
+    }
+
+    fn report_errors(&mut self) {
+        // whatever failed has already been skipped, so just log it
+        for e in self.source.take_errors() {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Manager;
+    use crate::{Scheduler, Sink, Time};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    struct Discard;
+
+    impl Sink for Discard {
+        fn samplerate(&self) -> f32 {
+            100.0
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn write(&mut self, _buffer: &[f32]) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn marks_wait_out_pauses() {
+        // keep hold of the scheduler, so we can pause it from out here
+        let slot: Rc<RefCell<Option<Scheduler>>> = Rc::new(RefCell::new(None));
+        let inner = slot.clone();
+        let mut manager: Manager<_, ()> = Manager::new(Discard, 10, move |sched| {
+            *inner.borrow_mut() = Some(sched);
+            futures_lite::future::pending()
+        });
+        manager.render(Time::frames(10)).unwrap();
+
+        let fired = Rc::new(Cell::new(false));
+        let fired_inner = fired.clone();
+        let mut slot = slot.borrow_mut();
+        let sched = slot.as_mut().unwrap();
+        sched.mark(Time::frames(50), move || fired_inner.set(true));
+
+        // silence while paused doesn't bring the mark any closer
+        sched.set_paused(true);
+        manager.render(Time::frames(200)).unwrap();
+        assert!(!fired.get());
+
+        sched.set_paused(false);
+        manager.render(Time::frames(30)).unwrap();
+        assert!(!fired.get());
+        manager.render(Time::frames(20)).unwrap();
+        assert!(fired.get());
+    }
 }
@@ -0,0 +1,514 @@
+use std::collections::VecDeque;
+
+use strict_yaml_rust::StrictYaml;
+
+// taps used to find peaks between samples, at 4x oversampling
+const TRUE_PEAK_TAPS: usize = 8;
+
+// where the agc splits bands, in Hz
+const AGC_CROSSOVERS: [f32; 2] = [250.0, 4000.0];
+// roughly how music shares its energy between those bands, in dB
+const AGC_BAND_OFFSETS: [f32; 3] = [-4.0, -3.0, -12.0];
+// frames between agc gain updates
+const AGC_BLOCK: usize = 64;
+
+// processing on everything a station plays, just before the sink
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MasterSettings {
+    pub agc: Option<AgcSettings>,
+    pub limiter: Option<LimiterSettings>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterSettings {
+    // highest true peak let through, in dBTP
+    pub ceiling: f32,
+    // in seconds
+    pub lookahead: f32,
+    pub release: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgcSettings {
+    // level to ride towards, in dBFS RMS
+    pub target: f32,
+    // most each band is turned up or down, in dB
+    pub max_gain: f32,
+    pub max_cut: f32,
+    // how slowly levels are followed, in seconds
+    pub speed: f32,
+    // bands quieter than this are left alone, in dBFS RMS
+    pub gate: f32,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            ceiling: -1.0,
+            lookahead: 0.005,
+            release: 0.1,
+        }
+    }
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self {
+            target: -20.0,
+            max_gain: 6.0,
+            max_cut: 12.0,
+            speed: 3.0,
+            gate: -50.0,
+        }
+    }
+}
+
+impl MasterSettings {
+    pub fn is_empty(&self) -> bool {
+        self.agc.is_none() && self.limiter.is_none()
+    }
+
+    pub(crate) fn update(&mut self, data: &StrictYaml) -> anyhow::Result<()> {
+        if data.is_badvalue() {
+            return Ok(());
+        }
+        crate::Definitions::check_keys(data, &["agc", "limiter"])?;
+
+        if let Some(on) = Self::switch(&data["limiter"], "limiter")? {
+            let mut limiter = self.limiter.unwrap_or_default();
+            let data = &data["limiter"];
+            if data.as_hash().is_some() {
+                crate::Definitions::check_keys(data, &["ceiling", "lookahead", "release"])?;
+                Self::update_number(data, "ceiling", &mut limiter.ceiling)?;
+                Self::update_time(data, "lookahead", &mut limiter.lookahead)?;
+                Self::update_time(data, "release", &mut limiter.release)?;
+            }
+            self.limiter = Some(limiter).filter(|_| on);
+        }
+
+        if let Some(on) = Self::switch(&data["agc"], "agc")? {
+            let mut agc = self.agc.unwrap_or_default();
+            let data = &data["agc"];
+            if data.as_hash().is_some() {
+                crate::Definitions::check_keys(
+                    data,
+                    &["target", "max-gain", "max-cut", "speed", "gate"],
+                )?;
+                Self::update_number(data, "target", &mut agc.target)?;
+                Self::update_number(data, "max-gain", &mut agc.max_gain)?;
+                Self::update_number(data, "max-cut", &mut agc.max_cut)?;
+                Self::update_time(data, "speed", &mut agc.speed)?;
+                Self::update_number(data, "gate", &mut agc.gate)?;
+            }
+            self.agc = Some(agc).filter(|_| on);
+        }
+
+        Ok(())
+    }
+
+    // a section is either on, off, or a dict of settings (which is on)
+    fn switch(data: &StrictYaml, k: &str) -> anyhow::Result<Option<bool>> {
+        if data.is_badvalue() {
+            return Ok(None);
+        }
+        if data.as_hash().is_some() {
+            return Ok(Some(true));
+        }
+        match data.as_str().map(|s| s.to_lowercase()).as_deref() {
+            Some("on" | "yes" | "true") => Ok(Some(true)),
+            Some("off" | "no" | "false") => Ok(Some(false)),
+            _ => anyhow::bail!("{} should be on, off, or settings", k),
+        }
+    }
+
+    fn update_number(data: &StrictYaml, k: &str, v: &mut f32) -> anyhow::Result<()> {
+        if let Some(s) = crate::Definitions::get_str(data, k)? {
+            *v = s
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} should be a number", k))?;
+        }
+        Ok(())
+    }
+
+    fn update_time(data: &StrictYaml, k: &str, v: &mut f32) -> anyhow::Result<()> {
+        if let Some(s) = crate::Definitions::get_str(data, k)? {
+            *v = crate::Definitions::parse_time(s)?;
+        }
+        Ok(())
+    }
+}
+
+// the master chain itself. agc first, then the limiter catches the rest.
+pub struct Master {
+    agc: Option<Agc>,
+    limiter: Option<Limiter>,
+    samplerate: f32,
+    channels: usize,
+}
+
+impl Master {
+    pub fn new(settings: &MasterSettings, samplerate: f32, channels: u16) -> Self {
+        Self {
+            agc: settings
+                .agc
+                .map(|s| Agc::new(&s, samplerate, channels as usize)),
+            limiter: settings
+                .limiter
+                .map(|s| Limiter::new(&s, samplerate, channels as usize)),
+            samplerate,
+            channels: channels as usize,
+        }
+    }
+
+    pub fn process(&mut self, buffer: &mut [f32]) {
+        if let Some(agc) = &mut self.agc {
+            agc.process(buffer);
+        }
+        if let Some(limiter) = &mut self.limiter {
+            limiter.process(buffer);
+        }
+    }
+
+    // how far behind its input the output is, in seconds
+    pub fn latency(&self) -> f32 {
+        let frames = self.limiter.as_ref().map(|l| l.delay()).unwrap_or(0);
+        frames as f32 / self.samplerate
+    }
+
+    // whatever audio is still held back, pushed out by silence
+    pub fn flush(&mut self) -> Vec<f32> {
+        let frames = self.limiter.as_ref().map(|l| l.delay()).unwrap_or(0);
+        let mut buffer = vec![0.0; frames * self.channels];
+        if let Some(limiter) = &mut self.limiter {
+            limiter.process(&mut buffer);
+        }
+        buffer
+    }
+}
+
+// a lookahead limiter that keeps even the peaks between samples down
+struct Limiter {
+    channels: usize,
+    ceiling: f32,
+    lookahead: usize,
+    release: f32,
+    // the last few samples, for finding true peaks
+    history: VecDeque<f32>,
+    interpolate: [[f32; TRUE_PEAK_TAPS]; 3],
+    // audio waiting for its gain to be known
+    delayed: VecDeque<f32>,
+    // increasing gains over the lookahead, with the frame they're for
+    minimums: VecDeque<(u64, f32)>,
+    released: f32,
+    // box filter over the lookahead, smoothing gain changes
+    smoothing: VecDeque<f32>,
+    smoothing_sum: f64,
+    frame: u64,
+}
+
+impl Limiter {
+    fn new(settings: &LimiterSettings, samplerate: f32, channels: usize) -> Self {
+        let lookahead = ((settings.lookahead * samplerate) as usize).max(1);
+
+        // windowed sinc, for points a quarter of the way between samples
+        let center = TRUE_PEAK_TAPS / 2 - 1;
+        let mut interpolate = [[0.0; TRUE_PEAK_TAPS]; 3];
+        for (i, taps) in interpolate.iter_mut().enumerate() {
+            let fraction = (i + 1) as f32 / 4.0;
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let t = center as f32 + fraction - k as f32;
+                let x = std::f32::consts::PI * t;
+                let sinc = if t == 0.0 { 1.0 } else { x.sin() / x };
+                let window = 0.5 * (1.0 + (x / (TRUE_PEAK_TAPS / 2) as f32).cos());
+                *tap = sinc * window;
+            }
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|t| *t /= sum);
+        }
+
+        let mut limiter = Self {
+            channels,
+            ceiling: f32::powf(10.0, settings.ceiling / 20.0),
+            lookahead,
+            release: coefficient(settings.release, samplerate),
+            history: vec![0.0; TRUE_PEAK_TAPS * channels].into(),
+            interpolate,
+            delayed: VecDeque::new(),
+            minimums: VecDeque::new(),
+            released: 1.0,
+            smoothing: vec![1.0; lookahead].into(),
+            smoothing_sum: lookahead as f64,
+            frame: 0,
+        };
+        let delay = limiter.delay();
+        limiter.delayed = vec![0.0; delay * channels].into();
+        limiter
+    }
+
+    // the lookahead, plus the peak finder looking back half its taps
+    fn delay(&self) -> usize {
+        self.lookahead + TRUE_PEAK_TAPS / 2
+    }
+
+    // highest peak, on or just after the center of the history
+    fn true_peak(&self) -> f32 {
+        let center = TRUE_PEAK_TAPS / 2 - 1;
+        let mut peak = 0.0f32;
+        for c in 0..self.channels {
+            let sample = |k: usize| self.history[k * self.channels + c];
+            peak = peak.max(sample(center).abs());
+            for taps in self.interpolate.iter() {
+                let v: f32 = taps.iter().enumerate().map(|(k, t)| t * sample(k)).sum();
+                peak = peak.max(v.abs());
+            }
+        }
+        peak
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_mut(self.channels) {
+            self.history.drain(..self.channels);
+            self.history.extend(frame.iter().copied());
+
+            // the gain needed for the audio coming out lookahead from now
+            let peak = self.true_peak();
+            let needed = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+            while self.minimums.back().filter(|m| m.1 >= needed).is_some() {
+                self.minimums.pop_back();
+            }
+            self.minimums.push_back((self.frame, needed));
+            while self.minimums[0].0 + (self.lookahead as u64) < self.frame {
+                self.minimums.pop_front();
+            }
+            let held = self.minimums[0].1;
+
+            // drop right away, but come back up slowly
+            self.released = held + (self.released - held) * self.release;
+            self.released = self.released.min(held);
+
+            // so the gain is down by the time that audio comes out
+            self.smoothing.push_back(self.released);
+            self.smoothing_sum += self.released as f64;
+            self.smoothing_sum -= self.smoothing.pop_front().unwrap_or(1.0) as f64;
+            let gain = (self.smoothing_sum / self.lookahead as f64) as f32;
+
+            for sample in frame.iter_mut() {
+                self.delayed.push_back(*sample);
+                *sample = self.delayed.pop_front().unwrap_or(0.0) * gain;
+            }
+            self.frame += 1;
+        }
+    }
+}
+
+// rides the level of a few bands towards a target, slowly
+struct Agc {
+    channels: usize,
+    settings: AgcSettings,
+    // one pole lowpass for each crossover, for each channel
+    alphas: [f32; 2],
+    lows: Vec<[f32; 2]>,
+    // mean square level of each band
+    levels: [f32; 3],
+    level_coef: f32,
+    // in dB, and as a plain multiplier
+    gains: [f32; 3],
+    multipliers: [f32; 3],
+    gain_coef: f32,
+    count: usize,
+}
+
+impl Agc {
+    fn new(settings: &AgcSettings, samplerate: f32, channels: usize) -> Self {
+        let alpha = |hz: f32| {
+            let rc = 1.0 / (2.0 * std::f32::consts::PI * hz);
+            let dt = 1.0 / samplerate;
+            dt / (rc + dt)
+        };
+        Self {
+            channels,
+            settings: *settings,
+            alphas: [alpha(AGC_CROSSOVERS[0]), alpha(AGC_CROSSOVERS[1])],
+            lows: vec![[0.0; 2]; channels],
+            // start from silence, so nothing moves until something is heard
+            levels: [0.0; 3],
+            level_coef: coefficient(settings.speed, samplerate),
+            gains: [0.0; 3],
+            multipliers: [1.0; 3],
+            gain_coef: coefficient(settings.speed, samplerate / AGC_BLOCK as f32),
+            count: 0,
+        }
+    }
+
+    fn update_gains(&mut self) {
+        let bands = self
+            .levels
+            .iter()
+            .zip(AGC_BAND_OFFSETS)
+            .zip(self.gains.iter_mut().zip(self.multipliers.iter_mut()));
+        for ((level, offset), (gain, multiplier)) in bands {
+            let level = 10.0 * level.max(1e-12).log10();
+            if level < self.settings.gate {
+                continue;
+            }
+            let wanted = (self.settings.target + offset - level)
+                .clamp(-self.settings.max_cut.abs(), self.settings.max_gain.abs());
+            *gain = wanted + (*gain - wanted) * self.gain_coef;
+            *multiplier = f32::powf(10.0, *gain / 20.0);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_mut(self.channels) {
+            if self.count == 0 {
+                self.update_gains();
+                self.count = AGC_BLOCK;
+            }
+            self.count -= 1;
+
+            let mut power = [0.0; 3];
+            for (c, sample) in frame.iter_mut().enumerate() {
+                // the bands add back up to exactly the input
+                let lows = &mut self.lows[c];
+                lows[0] += self.alphas[0] * (*sample - lows[0]);
+                lows[1] += self.alphas[1] * (*sample - lows[1]);
+                let bands = [lows[0], lows[1] - lows[0], *sample - lows[1]];
+
+                *sample = 0.0;
+                for ((band, multiplier), power) in
+                    bands.iter().zip(self.multipliers).zip(&mut power)
+                {
+                    *power += band * band;
+                    *sample += band * multiplier;
+                }
+            }
+
+            for (level, power) in self.levels.iter_mut().zip(power) {
+                let power = power / self.channels as f32;
+                *level = power + (*level - power) * self.level_coef;
+            }
+        }
+    }
+}
+
+// smoothing coefficient for a one-pole filter with this time constant
+fn coefficient(seconds: f32, samplerate: f32) -> f32 {
+    if seconds <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (seconds * samplerate)).exp()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AgcSettings, LimiterSettings, Master, MasterSettings};
+    use strict_yaml_rust::StrictYamlLoader;
+
+    // change in level from input to output over the last half second, in dB
+    fn agc_gain(level: f32) -> f32 {
+        let samplerate = 48000.0;
+        let settings = MasterSettings {
+            agc: Some(AgcSettings {
+                speed: 0.2,
+                ..Default::default()
+            }),
+            limiter: None,
+        };
+        let mut master = Master::new(&settings, samplerate, 1);
+
+        // a steady tone in the middle band, for a few seconds
+        let amplitude = f32::powf(10.0, level / 20.0) * std::f32::consts::SQRT_2;
+        let input: Vec<f32> = (0..96000)
+            .map(|i| amplitude * (i as f32 * 1000.0 / samplerate * std::f32::consts::TAU).sin())
+            .collect();
+        let mut output = input.clone();
+        master.process(&mut output);
+
+        let rms = |b: &[f32]| (b.iter().map(|s| s * s).sum::<f32>() / b.len() as f32).sqrt();
+        20.0 * (rms(&output[72000..]) / rms(&input[72000..])).log10()
+    }
+
+    #[test]
+    fn agc_rides_toward_target() {
+        // far too quiet, so turned up, but only by max-gain
+        let gain = agc_gain(-40.0);
+        assert!(gain > 5.0 && gain < 6.1, "quiet gain {}", gain);
+
+        // far too loud, so turned down, but only by max-cut
+        let gain = agc_gain(-3.0);
+        assert!(gain < -5.0 && gain > -12.1, "loud gain {}", gain);
+
+        // below the gate, so nothing changes at all
+        let gain = agc_gain(-70.0);
+        assert!(gain.abs() < 0.01, "gated gain {}", gain);
+    }
+
+    #[test]
+    fn settings_switch_and_update() {
+        let update = |settings: &mut MasterSettings, yaml: &str| {
+            let data = StrictYamlLoader::load_from_str(yaml).unwrap();
+            settings.update(&data[0])
+        };
+        let mut settings = MasterSettings::default();
+        assert!(settings.is_empty());
+
+        // on means the defaults, and settings turn things on too
+        update(&mut settings, "limiter: on").unwrap();
+        assert_eq!(settings.limiter, Some(LimiterSettings::default()));
+        update(&mut settings, "agc:\n  target: -18\n  speed: 0:01").unwrap();
+        let agc = settings.agc.unwrap();
+        assert_eq!((agc.target, agc.speed), (-18.0, 1.0));
+        assert_eq!(agc.max_gain, AgcSettings::default().max_gain);
+
+        // later updates only change what they mention
+        update(&mut settings, "limiter:\n  ceiling: -2").unwrap();
+        update(&mut settings, "limiter: yes").unwrap();
+        assert_eq!(settings.limiter.unwrap().ceiling, -2.0);
+        assert_eq!(settings.agc, Some(agc));
+
+        update(&mut settings, "agc: off\nlimiter: off").unwrap();
+        assert!(settings.is_empty());
+
+        assert!(update(&mut settings, "agc: maybe").is_err());
+        assert!(update(&mut settings, "agc:\n  loud: 11").is_err());
+        assert!(update(&mut settings, "compressor: on").is_err());
+    }
+
+    #[test]
+    fn limiter_holds_ceiling() {
+        let samplerate = 48000.0;
+        let settings = MasterSettings {
+            agc: None,
+            limiter: Some(LimiterSettings::default()),
+        };
+        let mut master = Master::new(&settings, samplerate, 2);
+
+        // a loud tone, then a sudden much louder burst
+        let mut buffer: Vec<f32> = (0..48000)
+            .flat_map(|i| {
+                let level = if (20000..24000).contains(&i) {
+                    4.0
+                } else {
+                    0.5
+                };
+                let v = level * (i as f32 * 0.3).sin();
+                [v, v]
+            })
+            .collect();
+        master.process(&mut buffer);
+        buffer.extend(master.flush());
+
+        let ceiling = f32::powf(10.0, -1.0 / 20.0);
+        assert!(buffer.iter().all(|s| s.abs() <= ceiling + 1e-4));
+
+        // quiet parts come through untouched, just later
+        let delay = (master.latency() * samplerate) as usize;
+        let expected = 0.5 * (1000.0f32 * 0.3).sin();
+        assert!((buffer[(1000 + delay) * 2] - expected).abs() < 1e-4);
+    }
+}
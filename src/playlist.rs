@@ -0,0 +1,102 @@
+use crate::Segment;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// how many played items to remember
+const HISTORY_LENGTH: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub segment: Segment,
+    // when it started, or when we expect it to, if we know
+    pub start: Option<SystemTime>,
+}
+
+// what a station has played, and what it will play next
+#[derive(Debug, Clone, Default)]
+pub struct Playlist {
+    inner: Arc<Mutex<PlaylistData>>,
+}
+
+#[derive(Debug, Default)]
+struct PlaylistData {
+    history: VecDeque<Entry>,
+    // already handed to the scheduler
+    scheduled: VecDeque<Entry>,
+    // asked for, but not scheduled yet
+    requested: Vec<Entry>,
+}
+
+impl Entry {
+    pub fn new(segment: Segment, start: Option<SystemTime>) -> Self {
+        Self { segment, start }
+    }
+
+    // start time in seconds since the unix epoch
+    pub fn timestamp(&self) -> Option<f64> {
+        let start = self.start?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        Some(start.as_secs_f64())
+    }
+}
+
+impl Playlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // recently played items, newest first
+    pub fn history(&self) -> Vec<Entry> {
+        self.inner
+            .lock()
+            .map(|d| d.history.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // items coming up, soonest first
+    pub fn queue(&self) -> Vec<Entry> {
+        self.inner
+            .lock()
+            .map(|d| {
+                d.scheduled
+                    .iter()
+                    .chain(d.requested.iter())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn schedule(&self, entry: Entry) {
+        if let Ok(mut d) = self.inner.lock() {
+            d.scheduled.push_back(entry);
+        }
+    }
+
+    // the oldest scheduled item has started
+    pub fn start(&self) {
+        if let Ok(mut d) = self.inner.lock() {
+            if let Some(mut entry) = d.scheduled.pop_front() {
+                entry.start = Some(SystemTime::now());
+                d.history.push_back(entry);
+                while d.history.len() > HISTORY_LENGTH {
+                    d.history.pop_front();
+                }
+            }
+        }
+    }
+
+    // everything scheduled was dropped
+    pub fn unschedule(&self) {
+        if let Ok(mut d) = self.inner.lock() {
+            d.scheduled.clear();
+        }
+    }
+
+    pub fn set_requested(&self, requested: Vec<Entry>) {
+        if let Ok(mut d) = self.inner.lock() {
+            d.requested = requested;
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// a windowed sinc resampler, in plain rust, for when libsamplerate isn't around
+
+// zero crossings of the filter on each side of center
+const ZERO_CROSSINGS: usize = 32;
+// filter table entries between each zero crossing
+const PHASES: usize = 128;
+// where the filter starts rolling off, as a fraction of the lower nyquist
+const CUTOFF: f64 = 0.91;
+// kaiser window shape, about 90dB down in the stopband
+const KAISER_BETA: f64 = 8.6;
+
+// same range of ratios libsamplerate accepts
+const MAX_RATIO: f64 = 256.0;
+
+#[derive(Debug, Clone)]
+pub struct Polyphase {
+    channels: usize,
+    // one side of the filter, from center out, sampled PHASES times per crossing
+    table: Vec<f32>,
+    // input waiting to be used, interleaved
+    history: Vec<f32>,
+    // input position of the next output, in frames into history
+    position: f64,
+    // frames into history where the real input ends, once it has
+    end: Option<f64>,
+    started: bool,
+}
+
+impl Polyphase {
+    pub fn new(channels: u16) -> Self {
+        let table = (0..=ZERO_CROSSINGS * PHASES + 1)
+            .map(|i| {
+                let u = i as f64 / PHASES as f64;
+                let x = std::f64::consts::PI * CUTOFF * u;
+                let sinc = if i == 0 { 1.0 } else { x.sin() / x };
+                let w = u / ZERO_CROSSINGS as f64;
+                let window = if w < 1.0 {
+                    bessel_i0(KAISER_BETA * (1.0 - w * w).sqrt()) / bessel_i0(KAISER_BETA)
+                } else {
+                    0.0
+                };
+                (sinc * window) as f32
+            })
+            .collect();
+        Self {
+            channels: channels as usize,
+            table,
+            history: Vec::new(),
+            position: 0.0,
+            end: None,
+            started: false,
+        }
+    }
+
+    // filter value at distance u from center, in zero crossings
+    fn kernel(&self, u: f64) -> f32 {
+        let t = u.abs() * PHASES as f64;
+        let i = t as usize;
+        if i + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let frac = (t - i as f64) as f32;
+        self.table[i] + (self.table[i + 1] - self.table[i]) * frac
+    }
+
+    // resample as much input as fits into output, with output
+    // samplerate = ratio * input samplerate. empty input means
+    // there is no more, and what's left is flushed out.
+    // returns input samples used and output samples made.
+    pub fn process(
+        &mut self,
+        ratio: f64,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<(usize, usize)> {
+        if !ratio.is_finite() || !(1.0 / MAX_RATIO..=MAX_RATIO).contains(&ratio) {
+            anyhow::bail!("bad resampling ratio {}", ratio);
+        }
+
+        // narrow the filter when going down, so nothing folds back
+        let scale = ratio.min(1.0);
+        let width = (ZERO_CROSSINGS as f64 / scale).ceil() as usize;
+        let channels = self.channels;
+        let frames = |v: &Vec<f32>| v.len() / channels;
+
+        // start with silence before, so the first output is centered on the first input
+        if !self.started {
+            self.history.resize(width * self.channels, 0.0);
+            self.position = width as f64;
+            self.started = true;
+        }
+
+        // take only as much input as this output needs
+        let outframes = output.len() / self.channels;
+        let mut used = 0;
+        if input.is_empty() {
+            if self.end.is_none() {
+                self.end = Some(frames(&self.history) as f64);
+                self.history
+                    .resize(self.history.len() + (width + 1) * self.channels, 0.0);
+            }
+        } else if self.end.is_none() {
+            let last = self.position + outframes.saturating_sub(1) as f64 / ratio;
+            let needed = (last.floor() as usize + width + 2).saturating_sub(frames(&self.history));
+            used = needed.min(input.len() / self.channels) * self.channels;
+            self.history.extend_from_slice(&input[..used]);
+        }
+
+        let gain = (CUTOFF * scale) as f32;
+        let mut made = 0;
+        while made < outframes {
+            if self.end.filter(|end| self.position >= *end).is_some() {
+                break;
+            }
+            let center = self.position.floor() as usize;
+            if center + width + 1 > frames(&self.history) {
+                break;
+            }
+
+            let out = &mut output[made * self.channels..(made + 1) * self.channels];
+            out.iter_mut().for_each(|s| *s = 0.0);
+            for j in (center + 1).saturating_sub(width)..=center + width {
+                let k = self.kernel((j as f64 - self.position) * scale) * gain;
+                let frame = &self.history[j * self.channels..(j + 1) * self.channels];
+                for (o, i) in out.iter_mut().zip(frame) {
+                    *o += i * k;
+                }
+            }
+
+            made += 1;
+            self.position += 1.0 / ratio;
+        }
+
+        // forget input that's behind the filter now
+        let drop = (self.position.floor() as usize).saturating_sub(width);
+        if drop > 0 {
+            self.history.drain(..drop * self.channels);
+            self.position -= drop as f64;
+            self.end = self.end.map(|end| end - drop as f64);
+        }
+
+        Ok((used, made * self.channels))
+    }
+}
+
+// modified bessel function of the first kind, for the kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..50 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod test {
+    use super::Polyphase;
+
+    // a sine sweep from 20Hz up to top, at this samplerate
+    fn sweep(samplerate: f32, top: f32, seconds: f32) -> Vec<f32> {
+        let len = (samplerate * seconds) as usize;
+        (0..len)
+            .map(|i| sweep_at(i as f64 / samplerate as f64, top, seconds))
+            .collect()
+    }
+
+    fn sweep_at(t: f64, top: f32, seconds: f32) -> f32 {
+        let rate = (top as f64 - 20.0) / seconds as f64;
+        let phase = 2.0 * std::f64::consts::PI * (20.0 * t + rate * t * t / 2.0);
+        (0.5 * phase.sin()) as f32
+    }
+
+    // feed input through in pieces, the way Resample does
+    fn run<F>(input: &[f32], ratio: f64, mut process: F) -> Vec<f32>
+    where
+        F: FnMut(&[f32], &mut [f32]) -> (usize, usize),
+    {
+        let mut output = vec![];
+        let mut buffer = vec![0.0; 1000];
+        let mut pos = 0;
+        loop {
+            let end = input.len().min(pos + (1000.0 / ratio) as usize + 2);
+            let (used, made) = process(&input[pos..end], &mut buffer);
+            output.extend_from_slice(&buffer[..made]);
+            pos += used;
+            if pos == input.len() && made == 0 {
+                return output;
+            }
+        }
+    }
+
+    // signal to noise against a perfect sweep, in dB, away from the ends
+    fn snr(output: &[f32], samplerate: f32, top: f32, seconds: f32) -> f32 {
+        let (mut signal, mut noise) = (0.0, 0.0);
+        let len = output.len();
+        for (i, v) in output.iter().enumerate().take(len * 9 / 10).skip(len / 10) {
+            let ideal = sweep_at(i as f64 / samplerate as f64, top, seconds);
+            signal += ideal * ideal;
+            noise += (v - ideal) * (v - ideal);
+        }
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn sweeps_match_libsamplerate() {
+        let seconds = 1.0;
+        for (from, to) in [
+            (44100.0, 48000.0),
+            (48000.0, 44100.0),
+            (22050.0, 48000.0),
+            (48000.0, 16000.0),
+        ] {
+            let ratio = to as f64 / from as f64;
+            // libsamplerate's fastest sinc keeps 80% of the band, so stay inside that
+            let top = 0.4 * f32::min(from, to) * 0.9;
+            let input = sweep(from, top, seconds);
+
+            let mut ours = Polyphase::new(1);
+            let output = run(&input, ratio, |i, o| ours.process(ratio, i, o).unwrap());
+            let expected = (input.len() as f64 * ratio).round() as usize;
+            assert!(output.len().abs_diff(expected) <= 1);
+            let ours = snr(&output, to, top, seconds);
+
+            #[cfg(feature = "libsamplerate")]
+            {
+                use crate::samplerate::{ConverterType, SampleRate};
+                let mut theirs = SampleRate::new(ConverterType::SincFastest, 1).unwrap();
+                let output = run(&input, ratio, |i, o| theirs.process(ratio, i, o).unwrap());
+                let theirs = snr(&output, to, top, seconds);
+                assert!(
+                    ours > theirs - 6.0,
+                    "{} -> {}: ours {:.1}dB, libsamplerate {:.1}dB",
+                    from,
+                    to,
+                    ours,
+                    theirs
+                );
+            }
+            assert!(ours > 90.0, "{} -> {}: ours {:.1}dB", from, to, ours);
+        }
+    }
+
+    #[test]
+    fn rejects_above_nyquist() {
+        // 10kHz can't be kept at 16kHz, so it should be filtered, not folded down
+        let input: Vec<f32> = (0..48000)
+            .map(|i| (2.0 * std::f64::consts::PI * 10000.0 * i as f64 / 48000.0).sin() as f32)
+            .collect();
+        let ratio = 16000.0 / 48000.0;
+        let mut resampler = Polyphase::new(1);
+        let output = run(&input, ratio, |i, o| {
+            resampler.process(ratio, i, o).unwrap()
+        });
+        // away from the ends, where the tone starts and stops suddenly
+        let middle = &output[output.len() / 10..output.len() * 9 / 10];
+        let peak = middle.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(20.0 * peak.log10() < -80.0);
+    }
+}
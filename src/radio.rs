@@ -1,12 +1,31 @@
-use crate::{Definitions, RandomMixer, Scheduler, SoftScheduler};
+use crate::control::{self, Command, ControlReceiver};
+use crate::definitions::SongAnalysis;
+use crate::playlist::{Entry, Playlist};
+use crate::{
+    Definitions, AnalysisCache, RandomMixer, Scheduler, Segment, SegmentKind, SoftScheduler,
+    Song, Time,
+};
 
 use rand::Rng;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::rc::Rc;
 
 pub struct Radio<F> {
     definitions: Definitions,
     scheduler: SoftScheduler,
-    metadata_callback: F,
+    // shared with marks on the scheduler, which call it when heard
+    metadata_callback: Rc<RefCell<F>>,
+    control: Option<ControlReceiver>,
+    playlist: Playlist,
+    // can this station keep everyone waiting?
+    realtime: bool,
+    // songs being listened to on another thread, for lengths and cues
+    analysis: Option<std::sync::mpsc::Receiver<SongAnalysis>>,
+
+    // segments asked for over the control channel, played before the clock
+    requested: VecDeque<(SegmentKind, Option<Song>)>,
 
     // some fun parameters
     intro_chance: f32,
@@ -25,40 +44,35 @@ pub struct Radio<F> {
     r_solo: RandomMixer<PathBuf>,
 }
 
-macro_rules! set_metadata {
-    ($self:expr, $fmt:expr) =>
-        (($self.metadata_callback)(
-            format!(concat!("{} - ", $fmt),
-                    $self.definitions.name.as_deref().unwrap_or("Sprunk"))
-        ));
-    ($self:expr, $fmt:expr, $($arg:tt)*) =>
-        (($self.metadata_callback)(
-            format!(concat!("{} - ", $fmt),
-                    $self.definitions.name.as_deref().unwrap_or("Sprunk"),
-                    $($arg)*))
-        );
-}
-
 impl<F> Radio<F>
 where
-    F: FnMut(String),
+    F: FnMut(Segment) + 'static,
 {
     pub fn new<PI, P>(
-        mut scheduler: Scheduler,
+        scheduler: Scheduler,
         paths: PI,
+        cache: AnalysisCache,
         metadata_callback: F,
     ) -> anyhow::Result<Self>
     where
         PI: Iterator<Item = P>,
         P: AsRef<std::path::Path>,
     {
-        // parameters: padding and over_volume and loudness
-        let scheduler = SoftScheduler::new(&mut scheduler, 0.5, 0.5, -14.0);
+        // parameters: padding and loudness
+        let scheduler = SoftScheduler::new(scheduler, 0.5, -14.0, cache);
+
+        let definitions = Definitions::open(paths)?;
 
         Ok(Self {
-            definitions: Definitions::open(paths)?,
+            definitions,
             scheduler,
-            metadata_callback,
+            metadata_callback: Rc::new(RefCell::new(metadata_callback)),
+            control: None,
+            playlist: Playlist::new(),
+            realtime: true,
+            analysis: None,
+
+            requested: VecDeque::new(),
 
             // parameters
             intro_chance: 0.3,
@@ -77,12 +91,106 @@ where
         })
     }
 
+    // take commands from here while playing
+    pub fn set_control(&mut self, control: ControlReceiver) {
+        self.playlist = control.playlist().clone();
+        self.control = Some(control);
+    }
+
+    // when not realtime, nobody is listening along, so songs are
+    // listened to before they play rather than in the background
+    pub fn set_realtime(&mut self, realtime: bool) {
+        self.realtime = realtime;
+    }
+
+    // use whatever has been learned about songs so far, and start
+    // listening to any new ones
+    fn analyze(&mut self) {
+        if let Some(receiver) = &self.analysis {
+            match receiver.try_recv() {
+                Ok(analysis) => self.definitions.add_analysis(analysis),
+                Err(std::sync::mpsc::TryRecvError::Empty) => return,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {}
+            }
+            self.analysis = None;
+        }
+
+        let songs = self.definitions.unanalyzed();
+        if songs.is_empty() {
+            return;
+        }
+        if !self.realtime {
+            self.definitions.add_analysis(Definitions::analyze(songs));
+            return;
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(Definitions::analyze(songs));
+        });
+        self.analysis = Some(receiver);
+    }
+
+    fn segment(&self, kind: SegmentKind) -> Segment {
+        Segment::new(self.definitions.name.as_deref().unwrap_or("Sprunk"), kind)
+    }
+
+    fn song_segment(&self, song: &Song) -> Segment {
+        let mut segment = self.segment(SegmentKind::Music);
+        segment.title = Some(song.metadata.title.clone());
+        segment.artist = Some(song.metadata.artist.clone());
+        segment.album = song.metadata.album.clone();
+        segment
+    }
+
+    // tell everyone about a scheduled item once it's heard,
+    // and wait for it to start
+    async fn announce(
+        &mut self,
+        (start, end): (Time, Time),
+        mut segment: Segment,
+    ) -> anyhow::Result<()> {
+        let samplerate = self.scheduler.samplerate();
+        segment.duration = Some(end.to_seconds(samplerate) - start.to_seconds(samplerate));
+        let expected = self.scheduler.system_time(start);
+        self.playlist
+            .schedule(Entry::new(segment.clone(), Some(expected)));
+        // stamp it as started when it's heard, not when it's rendered
+        let playlist = self.playlist.clone();
+        let callback = self.metadata_callback.clone();
+        self.scheduler.mark(start, move || {
+            playlist.start();
+            (callback.borrow_mut())(segment)
+        });
+        self.wait(start).await?;
+        Ok(())
+    }
+
+    fn update_requested(&mut self) {
+        let requested = self
+            .requested
+            .iter()
+            .map(|(kind, song)| {
+                let segment = if let Some(song) = song {
+                    self.song_segment(song)
+                } else {
+                    self.segment(*kind)
+                };
+                Entry::new(segment, None)
+            })
+            .collect();
+        self.playlist.set_requested(requested);
+    }
+
     pub async fn play_music(&mut self) -> anyhow::Result<()> {
-        let song = self
-            .r_music
-            .choose(self.definitions.music.iter(), |s| &s.path)
-            .ok_or_else(|| anyhow::anyhow!("no songs to play"))?;
+        // like the others, nothing to play is skipped over
+        if let Some(song) = self.r_music.choose(self.definitions.music.iter(), |s| &s.path) {
+            let song = song.clone();
+            self.play_song(song).await?;
+        }
+        Ok(())
+    }
 
+    pub async fn play_song(&mut self, song: Song) -> anyhow::Result<()> {
         let mut rng = rand::thread_rng();
         let mut over = None;
         if rng.gen::<f32>() < self.intro_chance {
@@ -90,7 +198,7 @@ where
             let mut choices = Vec::with_capacity(3);
 
             // general choices are always available
-            if let Some(p) = self.r_general.possibility(self.definitions.general.iter()) {
+            if let Some(p) = self.r_general.possibility(self.definitions.general.iter(), |p| p) {
                 choices.push(p);
             }
 
@@ -100,14 +208,14 @@ where
             if now.hour() >= 4 && now.hour() < 12 {
                 if let Some(p) = self
                     .r_time_morning
-                    .possibility(self.definitions.time_morning.iter())
+                    .possibility(self.definitions.time_morning.iter(), |p| p)
                 {
                     choices.push(p);
                 }
             } else if now.hour() >= 17 && now.hour() < 24 {
                 if let Some(p) = self
                     .r_time_evening
-                    .possibility(self.definitions.time_evening.iter())
+                    .possibility(self.definitions.time_evening.iter(), |p| p)
                 {
                     choices.push(p);
                 }
@@ -116,7 +224,7 @@ where
             // what about a song-specific intro?
             if let Some(p) = self
                 .r_intro
-                .possibility(self.definitions.get_intros(&song.metadata))
+                .possibility(self.definitions.get_intros(&song.metadata), |i| &i.path)
             {
                 choices.push(p.map(|i| &i.path));
             }
@@ -124,22 +232,21 @@ where
             // choose one of these!
             if choices.len() > 0 {
                 let possibility = choices.remove(rng.gen_range(0..choices.len()));
-                over = Some(possibility.accept(|p| p));
+                over = Some(possibility.accept());
             }
         }
 
-        self.scheduler
-            .add(&song.path, over, song.pre, Some(song.post), false)
-            .await?;
-        set_metadata!(self, "{} - {}", song.metadata.artist, song.metadata.title);
-        Ok(())
+        let times = self
+            .scheduler
+            .add(&song.path, over, song.pre.unwrap_or(0.0), song.post, false)?;
+        self.announce(times, self.song_segment(&song)).await
     }
 
     pub async fn play_ad(&mut self) -> anyhow::Result<()> {
         if let Some(ad) = self.r_ad.choose(self.definitions.ad.iter(), |p| p) {
             let over = self.r_to_ad.choose(self.definitions.to_ad.iter(), |p| p);
-            self.scheduler.add(&ad, over, 0.0, None, true).await?;
-            set_metadata!(self, "Advertisement");
+            let times = self.scheduler.add(&ad, over, 0.0, None, true)?;
+            self.announce(times, self.segment(SegmentKind::Ad)).await?;
         }
         Ok(())
     }
@@ -149,47 +256,155 @@ where
             let over = self
                 .r_to_news
                 .choose(self.definitions.to_news.iter(), |p| p);
-            self.scheduler.add(&news, over, 0.0, None, true).await?;
-            set_metadata!(self, "News");
+            let times = self.scheduler.add(&news, over, 0.0, None, true)?;
+            self.announce(times, self.segment(SegmentKind::News))
+                .await?;
         }
         Ok(())
     }
 
     pub async fn play_id(&mut self) -> anyhow::Result<()> {
         if let Some(id) = self.r_id.choose(self.definitions.id.iter(), |p| p) {
-            self.scheduler.add(&id, None, 0.0, None, false).await?;
-            set_metadata!(self, "Identification");
+            let times = self.scheduler.add(&id, None, 0.0, None, false)?;
+            self.announce(times, self.segment(SegmentKind::Id)).await?;
         }
         Ok(())
     }
 
     pub async fn play_mono(&mut self) -> anyhow::Result<()> {
         if let Some(solo) = self.r_solo.choose(self.definitions.solo.iter(), |p| p) {
-            self.scheduler.add(&solo, None, 0.0, None, false).await?;
-            set_metadata!(self, "Monologue");
+            let times = self.scheduler.add(&solo, None, 0.0, None, false)?;
+            self.announce(times, self.segment(SegmentKind::Mono))
+                .await?;
         }
         Ok(())
     }
 
+    pub async fn play_segment(&mut self, kind: SegmentKind) -> anyhow::Result<()> {
+        match kind {
+            SegmentKind::Music => self.play_music().await,
+            SegmentKind::Ad => self.play_ad().await,
+            SegmentKind::News => self.play_news().await,
+            SegmentKind::Id => self.play_id().await,
+            SegmentKind::Mono => self.play_mono().await,
+            SegmentKind::Zone => anyhow::bail!("only wow stations have zones"),
+        }
+    }
+
+    fn handle(&mut self, command: &Command) -> anyhow::Result<()> {
+        match command {
+            Command::Skip => {
+                self.scheduler.skip();
+                self.playlist.unschedule();
+            }
+            Command::Pause => self.scheduler.set_paused(true),
+            Command::Resume => self.scheduler.set_paused(false),
+            Command::Queue(query) => {
+                let song = self
+                    .definitions
+                    .find_song(query)
+                    .ok_or_else(|| anyhow::anyhow!("no song matches {:?}", query))?;
+                self.requested
+                    .push_back((SegmentKind::Music, Some(song.clone())));
+            }
+            Command::Ad | Command::Id => {
+                let kind = if *command == Command::Ad {
+                    SegmentKind::Ad
+                } else {
+                    SegmentKind::Id
+                };
+                if !self.definitions.has_segment(kind) {
+                    anyhow::bail!("station has nothing to play for {:?}", kind);
+                }
+                self.requested.push_back((kind, None));
+            }
+        }
+        self.update_requested();
+        Ok(())
+    }
+
+    // wait until this time, handling commands meanwhile.
+    // false if what we were waiting for was skipped.
+    async fn wait(&mut self, time: Time) -> anyhow::Result<bool> {
+        loop {
+            let scheduler = &mut self.scheduler;
+            let control = &mut self.control;
+            let request = futures_lite::future::or(
+                async move { scheduler.wait(time).await.map(|_| None) },
+                async move { Ok(Some(control::next_request(control).await)) },
+            )
+            .await?;
+
+            let Some(request) = request else {
+                return Ok(true);
+            };
+            let skipped = request.command == Command::Skip;
+            let result = self.handle(&request.command);
+            request.reply(result);
+            if skipped {
+                return Ok(false);
+            }
+        }
+    }
+
+    // wait until the next segment is needed
+    async fn ready(&mut self) -> anyhow::Result<()> {
+        while !self.wait(self.scheduler.ready_time()).await? {}
+        Ok(())
+    }
+
+    // play a segment from the clock, after anything asked for
+    async fn play_next(&mut self, kind: SegmentKind) -> anyhow::Result<()> {
+        self.ready().await?;
+        while let Some((kind, song)) = self.requested.pop_front() {
+            self.update_requested();
+            if let Some(song) = song {
+                self.play_song(song).await?;
+            } else {
+                self.play_segment(kind).await?;
+            }
+            self.ready().await?;
+        }
+        self.play_segment(kind).await
+    }
+
     pub async fn run(&mut self) -> anyhow::Result<()> {
         loop {
-            for ad_or_news in &[true, false] {
-                // reload failures can be ignored safely
-                // maaaaybe it should be logged. but it's fine.
-                let _ = self.definitions.reload();
+            // reload failures can be ignored safely
+            // maaaaybe it should be logged. but it's fine.
+            let _ = self.definitions.reload();
+            // only new songs need listening to, the rest are remembered
+            self.analyze();
 
-                for _ in 0..12 {
-                    self.play_music().await?;
-                }
+            // only music and station chatter crossfade, never ads or news
+            self.scheduler
+                .set_crossfade(self.definitions.crossfade, false);
+            // everything is trimmed alike, so gaps are the same between all
+            self.scheduler.set_trim(self.definitions.trim, true);
+            self.scheduler
+                .set_ducking(self.definitions.ducking.unwrap_or_default());
 
-                if *ad_or_news {
-                    self.play_ad().await?;
-                } else {
-                    self.play_news().await?;
+            let clock = self
+                .definitions
+                .clock
+                .clone()
+                .unwrap_or_else(Definitions::default_clock);
+
+            // make sure we don't spin forever on a clock that can't play
+            if !clock.iter().any(|s| {
+                s.count > 0 && s.chance > 0.0 && self.definitions.has_segment(s.kind)
+            }) {
+                anyhow::bail!("clock has no segments that can play");
+            }
+
+            for segment in clock.iter() {
+                if rand::thread_rng().gen::<f32>() >= segment.chance {
+                    continue;
                 }
 
-                self.play_id().await?;
-                self.play_mono().await?;
+                for _ in 0..segment.count {
+                    self.play_next(segment.kind).await?;
+                }
             }
         }
     }
@@ -2,14 +2,31 @@ use std::path::{Path, PathBuf};
 
 use strict_yaml_rust::{StrictYaml, StrictYamlLoader};
 
+use crate::control::ControlReceiver;
+use crate::encoder::Format;
 use crate::normalize::normalize;
 
 // in seconds
 const HOTSTART_WINDOW: f32 = 60.0 * 2.0;
 
+// how far to render at once while counting segments, in seconds
+const RENDER_SEGMENT_STEP: f32 = 0.1;
+
+// for file outputs, unless configured
+const DEFAULT_SAMPLERATE: u32 = 48000;
+const DEFAULT_CHANNELS: u16 = 2;
+
 #[derive(Debug, Clone)]
 pub struct RadioIndex {
     info: std::collections::HashMap<String, RadioInfo>,
+    cache: crate::AnalysisCache,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RenderLength {
+    // in seconds
+    Duration(f32),
+    Segments(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -17,6 +34,37 @@ pub struct RadioInfo {
     files: Vec<PathBuf>,
     typ: RadioType,
     output: Output,
+    samplerate: Option<u32>,
+    channels: Option<u16>,
+    control: Option<ControlAuth>,
+    master: crate::MasterSettings,
+}
+
+// credentials needed to send commands to a running station
+#[derive(Debug, Clone)]
+pub struct ControlAuth {
+    pub user: String,
+    // user:password, as sent with basic auth
+    secret: Vec<u8>,
+}
+
+impl ControlAuth {
+    pub fn new(user: &str, password: &str) -> Self {
+        ControlAuth {
+            user: user.to_owned(),
+            secret: format!("{}:{}", user, password).into_bytes(),
+        }
+    }
+
+    // do these user:password bytes match? this looks at every byte no
+    // matter where they differ, so the time taken gives nothing away.
+    pub fn check(&self, credentials: &[u8]) -> bool {
+        let mut diff = (credentials.len() != self.secret.len()) as u8;
+        for (i, b) in self.secret.iter().enumerate() {
+            diff |= b ^ credentials.get(i).copied().unwrap_or(0);
+        }
+        std::hint::black_box(diff) == 0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,13 +76,19 @@ pub enum RadioType {
 #[derive(Debug, Clone)]
 pub enum Output {
     System,
-    File(PathBuf),
+    File {
+        path: PathBuf,
+        format: Format,
+        samplerate: u32,
+        channels: u16,
+    },
     Icecast {
         mount: String,
         host: String,
         schema: String,
         user: String,
         password: Option<String>,
+        format: Format,
     },
 }
 
@@ -73,7 +127,14 @@ impl RadioIndex {
             station.update(&mount, k.1)?;
             info.insert(mount, station);
         }
-        Ok(Self { info: info })
+
+        // relative to the radio definitions list, and only kept in memory if unset
+        let cache = match crate::Definitions::get_str(data, "loudness-cache")? {
+            Some(cache) => crate::AnalysisCache::open(normalize(&base.join(cache)))?,
+            None => crate::AnalysisCache::new(),
+        };
+
+        Ok(Self { info: info, cache })
     }
 
     pub fn contains_key<S>(&self, station: S) -> bool
@@ -103,25 +164,124 @@ impl RadioIndex {
         name.ok_or_else(|| anyhow::anyhow!("station has no name"))
     }
 
-    fn play_inner<S, F>(&self, typ: RadioType, sink: S, bufsize: usize, files: Vec<PathBuf>, hotstart: bool, metadata: F) -> anyhow::Result<()> where S: crate::Sink, F: FnMut(String) + 'static {
+    // who may control this station, if anyone
+    pub fn control_auth<S>(&self, station: S) -> Option<&ControlAuth>
+    where
+        S: AsRef<str>,
+    {
+        self.info.get(station.as_ref())?.control.as_ref()
+    }
+
+    pub fn analysis_cache(&self) -> &crate::AnalysisCache {
+        &self.cache
+    }
+
+    // every media file played by normal stations, without duplicates
+    pub fn media_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = std::collections::BTreeSet::new();
+        for stationdef in self.info.values() {
+            if let RadioType::Normal = stationdef.typ {
+                let defs = crate::Definitions::open(stationdef.files.iter())?;
+                paths.extend(defs.all_paths().cloned());
+            }
+        }
+        Ok(paths.into_iter().collect())
+    }
+
+    // every song played by normal stations, by station name
+    // with the directory song paths are written relative to
+    pub fn songs(&self) -> anyhow::Result<Vec<(String, PathBuf, Vec<crate::Song>)>> {
+        let mut stations: Vec<_> = self.info.iter().collect();
+        stations.sort_by(|a, b| a.0.cmp(b.0));
+        let mut songs = vec![];
+        for (station, stationdef) in stations {
+            if let RadioType::Normal = stationdef.typ {
+                let defs = crate::Definitions::open(stationdef.files.iter())?;
+                let prefix = match stationdef.files.first() {
+                    Some(file) => crate::Definitions::media_prefix(file)?,
+                    None => PathBuf::new(),
+                };
+                songs.push((station.clone(), prefix, defs.music));
+            }
+        }
+        Ok(songs)
+    }
+
+    // every problem with every station, by station name
+    pub fn lint(&self) -> Vec<(String, Vec<anyhow::Error>)> {
+        let mut stations: Vec<_> = self.info.iter().collect();
+        stations.sort_by(|a, b| a.0.cmp(b.0));
+        stations
+            .into_iter()
+            .map(|(station, stationdef)| {
+                let problems = match stationdef.typ {
+                    RadioType::Normal => crate::Definitions::lint(stationdef.files.iter()),
+                    RadioType::Wow => crate::wow::Definitions::lint(stationdef.files.iter()),
+                };
+                (station.clone(), problems.unwrap_or_else(|e| vec![e]))
+            })
+            .collect()
+    }
+
+    fn manager<S, F>(
+        &self,
+        stationdef: &RadioInfo,
+        sink: S,
+        bufsize: usize,
+        realtime: bool,
+        control: Option<ControlReceiver>,
+        metadata: F,
+    ) -> crate::Manager<S, ()>
+    where
+        S: crate::Sink,
+        F: FnMut(crate::Segment) + 'static,
+    {
+        let cache = self.cache.clone();
+        let typ = stationdef.typ.clone();
+        let files = stationdef.files.clone();
         let mut manager = crate::Manager::new(sink, bufsize, move |sched| async move {
             match typ {
                 RadioType::Normal => {
-                    let mut radio = crate::Radio::new(sched, files.iter(), metadata)?;
+                    let mut radio = crate::Radio::new(sched, files.iter(), cache, metadata)?;
+                    radio.set_realtime(realtime);
+                    if let Some(control) = control {
+                        radio.set_control(control);
+                    }
                     radio.run().await
-                },
+                }
                 RadioType::Wow => {
                     let mut radio = crate::wow::Radio::new(sched, files.iter(), metadata)?;
+                    if let Some(control) = control {
+                        radio.set_control(control);
+                    }
                     radio.run().await
-                },
+                }
             }
         });
+        manager.set_master(&stationdef.master);
+        manager
+    }
+
+    fn play_inner<S, F>(
+        &self,
+        stationdef: &RadioInfo,
+        sink: S,
+        bufsize: usize,
+        hotstart: bool,
+        control: Option<ControlReceiver>,
+        metadata: F,
+    ) -> anyhow::Result<()>
+    where
+        S: crate::Sink,
+        F: FnMut(crate::Segment) + 'static,
+    {
+        let mut manager = self.manager(stationdef, sink, bufsize, true, control, metadata);
 
         if hotstart {
             use rand::Rng;
             // advance a random amount
             let amt = HOTSTART_WINDOW * rand::thread_rng().gen::<f32>();
-            manager.skip(amt);
+            manager.skip(amt)?;
         }
 
         manager.advance_to_end()
@@ -132,24 +292,70 @@ impl RadioIndex {
         station: S,
         output: Option<Box<dyn crate::Sink>>,
         hotstart: bool,
+        control: Option<ControlReceiver>,
         metadata: F,
     ) -> anyhow::Result<()>
     where
         S: AsRef<str>,
-        F: FnMut(String) + 'static,
+        F: FnMut(crate::Segment) + 'static,
     {
         let bufsize = 24000;
         let stationdef = self
             .info
             .get(station.as_ref())
             .ok_or_else(|| anyhow::anyhow!("could not find station"))?;
-        let sink = output
-            .map(Ok)
-            .unwrap_or_else(|| stationdef.output.to_sink(bufsize))?;
-        let files = stationdef.files.clone();
-        let typ = stationdef.typ.clone();
+        let sink = output.map(Ok).unwrap_or_else(|| {
+            let mut output = stationdef.output.clone();
+            output.set_audio(stationdef.samplerate, stationdef.channels);
+            output.to_sink(bufsize)
+        })?;
+        self.play_inner(stationdef, sink, bufsize, hotstart, control, metadata)
+    }
+
+    // play a station into output as fast as possible, from the start,
+    // until length is reached
+    pub fn render<S, F>(
+        &self,
+        station: S,
+        output: Box<dyn crate::Sink>,
+        length: RenderLength,
+        mut metadata: F,
+    ) -> anyhow::Result<()>
+    where
+        S: AsRef<str>,
+        F: FnMut(crate::Segment) + 'static,
+    {
+        let bufsize = 24000;
+        let stationdef = self
+            .info
+            .get(station.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("could not find station"))?;
+
+        // every segment starts with new metadata
+        let started = std::rc::Rc::new(std::cell::Cell::new(0));
+        let started_inner = started.clone();
+        let mut manager = self.manager(stationdef, output, bufsize, false, None, move |m| {
+            started_inner.set(started_inner.get() + 1);
+            metadata(m);
+        });
+
+        match length {
+            RenderLength::Duration(seconds) => manager.render(seconds)?,
+            RenderLength::Segments(count) => {
+                // stop as soon as the segment after the last one starts
+                while started.get() <= count && !manager.is_finished() {
+                    manager.render(RENDER_SEGMENT_STEP)?;
+                }
+            }
+        }
 
-        self.play_inner(typ, sink, bufsize, files, hotstart, metadata)
+        // the station only stops early if something went wrong
+        if manager.is_finished() {
+            manager.advance_to_end()?;
+            Ok(())
+        } else {
+            manager.finish()
+        }
     }
 }
 
@@ -159,6 +365,10 @@ impl RadioInfo {
             files: Vec::new(),
             typ: RadioType::Normal,
             output: Output::System,
+            samplerate: None,
+            channels: None,
+            control: None,
+            master: crate::MasterSettings::default(),
         }
     }
 
@@ -166,6 +376,35 @@ impl RadioInfo {
         self.typ.update(&data["type"])?;
         self.output.update_icecast(mount, &data["icecast"])?;
         self.output.update(&data["output"])?;
+        self.update_control(&data["control"])?;
+        self.master.update(&data["master"])?;
+        if let Some(samplerate) = crate::Definitions::get_str(data, "samplerate")? {
+            self.samplerate = Some(
+                samplerate
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("samplerate should be a number"))?,
+            );
+        }
+        if let Some(channels) = crate::Definitions::get_str(data, "channels")? {
+            self.channels = Some(
+                channels
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("channels should be a number"))?,
+            );
+        }
+        Ok(())
+    }
+
+    fn update_control(&mut self, data: &StrictYaml) -> anyhow::Result<()> {
+        if data.is_badvalue() {
+            return Ok(());
+        }
+
+        let user = data["user"].as_str().unwrap_or("admin");
+        let password = data["password"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("control needs a password"))?;
+        self.control = Some(ControlAuth::new(user, password));
         Ok(())
     }
 }
@@ -203,12 +442,41 @@ impl Output {
             "system" => Output::System,
             "file" => {
                 let fname = arg.ok_or_else(|| anyhow::anyhow!("file output expects value"))?;
-                Output::File(fname.into())
+                Output::File {
+                    path: fname.into(),
+                    format: Self::format_for(Path::new(fname)),
+                    samplerate: DEFAULT_SAMPLERATE,
+                    channels: DEFAULT_CHANNELS,
+                }
+            }
+            _ => {
+                // explicit formats, like flac:out.flac
+                let format =
+                    Format::from_name(typ).ok_or_else(|| anyhow::anyhow!("bad output value"))?;
+                let fname = arg.ok_or_else(|| anyhow::anyhow!("file output expects value"))?;
+                Output::File {
+                    path: fname.into(),
+                    format,
+                    samplerate: DEFAULT_SAMPLERATE,
+                    channels: DEFAULT_CHANNELS,
+                }
             }
-            _ => anyhow::bail!("bad output value"),
         })
     }
 
+    // change the sample rate or channel count, for outputs that have them
+    pub fn set_audio(&mut self, samplerate: Option<u32>, channels: Option<u16>) {
+        if let Output::File {
+            samplerate: ref mut r,
+            channels: ref mut c,
+            ..
+        } = *self
+        {
+            *r = samplerate.unwrap_or(*r);
+            *c = channels.unwrap_or(*c);
+        }
+    }
+
     fn update(&mut self, data: &StrictYaml) -> anyhow::Result<()> {
         if data.is_badvalue() {
             return Ok(());
@@ -233,30 +501,50 @@ impl Output {
         let schema = data["schema"].as_str().unwrap_or("http").to_owned();
         let user = data["user"].as_str().unwrap_or("source").to_owned();
         let password = data["password"].as_str().map(|s| s.to_owned());
+        // use the format given, or guess from the mount name
+        let format = if let Some(name) = data["format"].as_str() {
+            Format::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("unrecognized icecast format: {:?}", name))?
+        } else {
+            Self::format_for(Path::new(mount))
+        };
         *self = Output::Icecast {
             mount: fullmount,
             host,
             schema,
             user,
             password,
+            format,
         };
         Ok(())
     }
 
+    // pick a format by file extension, defaulting to mp3
+    fn format_for(path: &Path) -> Format {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(Format::from_name)
+            .unwrap_or(Format::Mp3)
+    }
+
     pub fn to_sink(&self, bufsize: usize) -> anyhow::Result<Box<dyn crate::Sink>> {
         match *self {
             Output::System => Ok(Box::new(crate::sink::System::new(bufsize)?)),
-            Output::File(ref fname) => {
-                // all files are mp3 I guess
-                let encoder = crate::encoder::Mp3::new(48000, None, None)?;
-                let file = std::fs::File::create(fname)?;
-                Ok(Box::new(crate::sink::Stream::new(file, encoder)))
+            Output::File {
+                ref path,
+                ref format,
+                samplerate,
+                channels,
+            } => {
+                let encoder = format.encoder(samplerate, channels)?;
+                Ok(Box::new(crate::sink::File::create(path, encoder)?))
             }
             Output::Icecast {
                 ref mount,
                 ref host,
                 ref user,
                 ref password,
+                ref format,
                 // schema unused. maybe we should use it eventually...
                 ..
             } => {
@@ -264,7 +552,7 @@ impl Output {
                 for addr in host.to_socket_addrs()? {
                     let ip = format!("{}", addr.ip());
                     let port = addr.port();
-                    let encoder = crate::encoder::Mp3::new(48000, None, None)?;
+                    let encoder = format.encoder(DEFAULT_SAMPLERATE, DEFAULT_CHANNELS)?;
                     if let Ok(sink) = crate::sink::Shout::new(
                         encoder,
                         &ip,
@@ -1,39 +1,83 @@
+use std::collections::HashMap;
+
 use rand::seq::IteratorRandom;
 
+// how many recent picks to avoid, by default
+const DEFAULT_WINDOW: usize = 16;
+
 pub struct RandomMixer<K> {
-    _tmp: std::collections::HashMap<K, u8>,
+    window: usize,
+    // how many things we've accepted so far
+    played: u64,
+    // when each key was last accepted, in terms of played
+    history: HashMap<K, u64>,
 }
 
 pub struct Possibility<'a, T, K> {
     mixer: &'a mut RandomMixer<K>,
+    key: K,
     value: T,
 }
 
 impl<K> RandomMixer<K> {
     pub fn new() -> Self {
+        Self::with_window(DEFAULT_WINDOW)
+    }
+
+    pub fn with_window(window: usize) -> Self {
         Self {
-            _tmp: std::collections::HashMap::new(),
+            window,
+            played: 0,
+            history: HashMap::new(),
+        }
+    }
+
+    fn is_recent(&self, key: &K, window: usize) -> bool
+    where
+        K: std::cmp::Eq + std::hash::Hash,
+    {
+        if let Some(last) = self.history.get(key) {
+            self.played - last < window as u64
+        } else {
+            false
         }
     }
 
-    pub fn possibility<I, T>(&mut self, it: I) -> Option<Possibility<T, K>>
+    pub fn possibility<I, T, F>(&mut self, it: I, f: F) -> Option<Possibility<'_, T, K>>
     where
         I: Iterator<Item = T>,
+        F: Fn(&T) -> &K,
+        K: std::cmp::Eq + std::hash::Hash + Clone,
     {
-        it.choose(&mut rand::thread_rng())
-            .map(move |v| Possibility {
-                mixer: self,
-                value: v,
-            })
+        let mut candidates: Vec<T> = it.collect();
+
+        // if the pool is too small for our window, shrink the window
+        // so there's always a choice to be made. Half the pool keeps
+        // things from falling into a fixed rotation.
+        let distinct = {
+            let mut seen = std::collections::HashSet::with_capacity(candidates.len());
+            candidates.iter().filter(|c| seen.insert(f(c))).count()
+        };
+        let window = self.window.min(distinct / 2);
+
+        candidates.retain(|c| !self.is_recent(f(c), window));
+
+        let idx = (0..candidates.len()).choose(&mut rand::thread_rng())?;
+        let value = candidates.swap_remove(idx);
+        Some(Possibility {
+            key: f(&value).clone(),
+            mixer: self,
+            value,
+        })
     }
 
     pub fn choose<I, T, F>(&mut self, it: I, f: F) -> Option<T>
     where
         I: Iterator<Item = T>,
         F: Fn(&T) -> &K,
-        K: std::cmp::Eq + std::hash::Hash,
+        K: std::cmp::Eq + std::hash::Hash + Clone,
     {
-        self.possibility(it).map(|p| p.accept(f))
+        self.possibility(it, f).map(|p| p.accept())
     }
 }
 
@@ -44,15 +88,57 @@ impl<'a, T, K> Possibility<'a, T, K> {
     {
         Possibility {
             mixer: self.mixer,
+            key: self.key,
             value: f(self.value),
         }
     }
 
-    pub fn accept<F>(self, _f: F) -> T
+    pub fn accept(self) -> T
     where
-        F: Fn(&T) -> &K,
         K: std::cmp::Eq + std::hash::Hash,
     {
+        self.mixer.played += 1;
+        self.mixer.history.insert(self.key, self.mixer.played);
         self.value
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::RandomMixer;
+
+    #[test]
+    fn no_repeats_within_window() {
+        let pool: Vec<u32> = (0..10).collect();
+        let mut mixer = RandomMixer::with_window(4);
+        let mut recent = std::collections::VecDeque::new();
+        for _ in 0..1000 {
+            let v = *mixer.choose(pool.iter(), |v| v).unwrap();
+            assert!(!recent.contains(&v), "{} repeated within window", v);
+            recent.push_back(v);
+            if recent.len() > 4 {
+                recent.pop_front();
+            }
+        }
+    }
+
+    #[test]
+    fn small_pools() {
+        let mut mixer = RandomMixer::with_window(16);
+        assert!(mixer.choose(std::iter::empty::<&u32>(), |v| v).is_none());
+
+        let one = [1u32];
+        for _ in 0..10 {
+            assert_eq!(mixer.choose(one.iter(), |v| v), Some(&1));
+        }
+
+        // two things should alternate, and never repeat
+        let two = [2u32, 3];
+        let mut last = *mixer.choose(two.iter(), |v| v).unwrap();
+        for _ in 0..10 {
+            let next = *mixer.choose(two.iter(), |v| v).unwrap();
+            assert_ne!(last, next);
+            last = next;
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use async_executor::{LocalExecutor, Task};
@@ -6,6 +6,9 @@ use async_oneshot::{oneshot, Sender};
 
 use crate::Source;
 
+// something to do once the audio at a given time is heard
+pub type Mark = Box<dyn FnOnce()>;
+
 #[derive(Clone, Copy, Debug)]
 pub struct Time {
     frames: u64,
@@ -15,6 +18,10 @@ pub struct Time {
 pub struct Scheduler {
     data: Rc<RefCell<SchedulerData>>,
     executor: Rc<RefCell<LocalExecutor<'static>>>,
+    errors: Rc<RefCell<Vec<anyhow::Error>>>,
+    marks: Rc<RefCell<Vec<(u64, Mark)>>>,
+    // seconds between rendering audio and it being heard
+    latency: Rc<Cell<f32>>,
     samplerate: f32,
     channels: u16,
 }
@@ -22,6 +29,12 @@ pub struct Scheduler {
 pub struct SchedulerSource {
     data: Rc<RefCell<SchedulerData>>,
     executor: Rc<RefCell<LocalExecutor<'static>>>,
+    // errors from sources we had to drop, shared with subschedulers
+    errors: Rc<RefCell<Vec<anyhow::Error>>>,
+    // marks whose time has been rendered, shared with subschedulers
+    marks: Rc<RefCell<Vec<(u64, Mark)>>>,
+    // how long until rendered audio is heard, shared with subschedulers
+    latency: Rc<Cell<f32>>,
     buffer: Vec<f32>,
     samplerate: f32,
     channels: u16,
@@ -30,10 +43,14 @@ pub struct SchedulerSource {
 struct SchedulerData {
     offset: u64,
     timers: Vec<(u64, Sender<()>)>,
+    marks: Vec<(u64, Mark)>,
     scheduled: Vec<(u64, Box<dyn Source>)>,
     active: Vec<Box<dyn Source>>,
     volume: f32,
     ramps: Vec<(u64, f32)>,
+    // drop everything playing at this frame
+    stop: Option<u64>,
+    paused: bool,
 }
 
 pub struct SchedulerTask<T> {
@@ -117,24 +134,44 @@ impl Scheduler {
     }
     
     pub fn new_with_volume(samplerate: f32, channels: u16, volume: f32) -> (Scheduler, SchedulerSource) {
+        Self::new_shared(samplerate, channels, volume, Default::default(), Default::default(), Default::default())
+    }
+
+    fn new_shared(
+        samplerate: f32,
+        channels: u16,
+        volume: f32,
+        errors: Rc<RefCell<Vec<anyhow::Error>>>,
+        marks: Rc<RefCell<Vec<(u64, Mark)>>>,
+        latency: Rc<Cell<f32>>,
+    ) -> (Scheduler, SchedulerSource) {
         let data = Rc::new(RefCell::new(SchedulerData {
             offset: 0,
             timers: Vec::with_capacity(10),
+            marks: Vec::new(),
             scheduled: Vec::with_capacity(10),
             active: Vec::with_capacity(10),
             volume,
             ramps: Vec::with_capacity(2),
+            stop: None,
+            paused: false,
         }));
         let executor = Rc::new(RefCell::new(LocalExecutor::new()));
         let scheduler = Scheduler {
             data: data.clone(),
             executor: executor.clone(),
+            errors: errors.clone(),
+            marks: marks.clone(),
+            latency: latency.clone(),
             samplerate,
             channels,
         };
         let source = SchedulerSource {
             data,
             executor,
+            errors,
+            marks,
+            latency,
             buffer: Vec::new(),
             samplerate,
             channels,
@@ -155,15 +192,49 @@ impl Scheduler {
     }
 
     pub fn subscheduler_with_volume(&mut self, volume: f32) -> Scheduler {
-        let (sched, src) = Scheduler::new_with_volume(self.samplerate, self.channels, volume);
-        let mut subdata = sched.data.borrow_mut();
-        let mut data = self.data.borrow_mut();
-        subdata.offset = data.offset;
-        data.active.push(Box::new(src));
-        drop(subdata);
+        let (sched, src) = self.detached_subscheduler(volume);
+        self.attach(src);
         sched
     }
 
+    // a subscheduler that isn't playing yet, so its source can be wrapped
+    // in something else first. play it with attach().
+    pub fn detached_subscheduler(&mut self, volume: f32) -> (Scheduler, SchedulerSource) {
+        let (sched, src) = Scheduler::new_shared(
+            self.samplerate,
+            self.channels,
+            volume,
+            self.errors.clone(),
+            self.marks.clone(),
+            self.latency.clone(),
+        );
+        sched.data.borrow_mut().offset = self.data.borrow().offset;
+        (sched, src)
+    }
+
+    // play a source from right now. it must already match this
+    // scheduler's samplerate and channels.
+    pub fn attach<S>(&mut self, src: S)
+    where
+        S: Source + 'static,
+    {
+        self.data.borrow_mut().active.push(Box::new(src));
+    }
+
+    // the time this scheduler has played up to
+    pub fn now(&self) -> Time {
+        Time::frames(self.data.borrow().offset)
+    }
+
+    // when a time will be heard, going by the system clock
+    pub fn system_time<T>(&self, time: T) -> std::time::SystemTime
+    where
+        T: Into<Time>,
+    {
+        let ahead = time.into().to_seconds(self.samplerate) - self.now().to_seconds(self.samplerate) + self.latency.get();
+        std::time::SystemTime::now() + std::time::Duration::from_secs_f32(ahead.max(0.0))
+    }
+
     pub fn add<T, S>(&mut self, start: T, src: S) -> Option<Time>
     where
         T: Into<Time>,
@@ -177,6 +248,16 @@ impl Scheduler {
         end
     }
 
+    // call f once the audio at this time has made it out of the sink
+    pub fn mark<T, F>(&mut self, time: T, f: F)
+    where
+        T: Into<Time>,
+        F: FnOnce() + 'static,
+    {
+        let time = time.into().to_frames(self.samplerate);
+        self.data.borrow_mut().marks.push((time, Box::new(f)));
+    }
+
     pub fn run<F, Fut, T>(self, f: F) -> SchedulerTask<T>
     where
         F: FnOnce(Scheduler) -> Fut + 'static,
@@ -246,6 +327,39 @@ impl Scheduler {
         self.add_ramp_point(start + duration, volume);
         start + duration
     }
+
+    // forget any volume changes from this time on
+    pub fn cancel_volume<T>(&mut self, start: T)
+    where
+        T: Into<Time>,
+    {
+        let start = start.into().to_frames(self.samplerate);
+        self.data.borrow_mut().ramps.retain(|r| r.0 < start);
+    }
+
+    // hold everything where it is, and play silence until unpaused
+    pub fn set_paused(&mut self, paused: bool) {
+        self.data.borrow_mut().paused = paused;
+    }
+
+    // silence and drop everything playing or scheduled at this time.
+    // anything added afterwards plays as usual.
+    pub fn stop<T>(&mut self, time: T)
+    where
+        T: Into<Time>,
+    {
+        let time = time.into().to_frames(self.samplerate);
+        let mut data = self.data.borrow_mut();
+        data.scheduled.retain(|(start, _)| *start < time);
+        data.marks.retain(|(start, _)| *start < time);
+        data.stop = Some(time);
+    }
+}
+
+impl<T> SchedulerTask<T> {
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
 }
 
 impl SchedulerSource {
@@ -253,6 +367,26 @@ impl SchedulerSource {
         let exec = self.executor.borrow();
         futures_lite::future::block_on(exec.run(task.task))
     }
+
+    // errors from any sources that failed and were dropped since last time
+    pub fn take_errors(&mut self) -> Vec<anyhow::Error> {
+        std::mem::take(&mut *self.errors.borrow_mut())
+    }
+
+    // how far the scheduler has rendered. this stands still while paused.
+    pub fn now(&self) -> Time {
+        Time::frames(self.data.borrow().offset)
+    }
+
+    // how long rendered audio takes to be heard, for system_time
+    pub fn set_latency(&mut self, seconds: f32) {
+        self.latency.set(seconds);
+    }
+
+    // marks rendered since last time, and the frame each one is for
+    pub fn take_marks(&mut self) -> Vec<(u64, Mark)> {
+        std::mem::take(&mut *self.marks.borrow_mut())
+    }
 }
 
 impl Source for SchedulerSource {
@@ -268,15 +402,27 @@ impl Source for SchedulerSource {
         None
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
         self.buffer.resize(buffer.len(), 0.0);
         buffer.iter_mut().for_each(|m| *m = 0.0);
 
         let data = self.data.borrow();
         let offset = data.offset;
         let end = offset + buffer.len() as u64 / self.channels as u64;
+        let stop = data.stop.filter(|s| !data.paused && *s > offset && *s < end);
         drop(data); // so we can borrow it in try_tick()
 
+        // stopping in this buffer, so fill up to the stop, then after it
+        if let Some(stop) = stop {
+            let split = (stop - offset) as usize * self.channels as usize;
+            let (before, after) = buffer.split_at_mut(split);
+            let avail = self.fill(before)?;
+            if avail < split {
+                return Ok(avail);
+            }
+            return Ok(split + self.fill(after)?);
+        }
+
         loop {
             let mut go_again = false;
 
@@ -285,9 +431,10 @@ impl Source for SchedulerSource {
                 go_again = true;
             }
 
+            // while paused, time stands still and no timers go off
             let mut data = self.data.borrow_mut();
             let mut i = 0;
-            while i != data.timers.len() {
+            while !data.paused && i != data.timers.len() {
                 let (ref mut start, _) = data.timers[i];
                 if *start < end {
                     let (_, send) = data.timers.remove(i);
@@ -305,6 +452,26 @@ impl Source for SchedulerSource {
 
         let mut data = self.data.borrow_mut();
 
+        if data.paused {
+            return Ok(buffer.len());
+        }
+
+        if data.stop.filter(|s| *s <= offset).is_some() {
+            data.active.clear();
+            data.stop = None;
+        }
+
+        // hand off marks for this buffer, for whoever writes it out
+        let mut i = 0;
+        while i != data.marks.len() {
+            if data.marks[i].0 < end {
+                let mark = data.marks.remove(i);
+                self.marks.borrow_mut().push(mark);
+            } else {
+                i += 1;
+            }
+        }
+
         // do we have anything to do, even?
         if data.active.len() == 0 && data.scheduled.len() == 0 && data.timers.len() == 0 {
             // we don't. but we might not be done!
@@ -312,17 +479,23 @@ impl Source for SchedulerSource {
                 // the scheduler side of this still exists
                 // so we might get more scheduled things later
                 data.offset = end;
-                return buffer.len();
+                return Ok(buffer.len());
             } else {
                 // the scheduler side has been dropped. we're done.
-                return 0;
+                return Ok(0);
             }
         }
 
         // render our active sources
         let mut i = 0;
         while i != data.active.len() {
-            let avail = data.active[i].force_fill(&mut self.buffer);
+            // a failed source is dropped, but not before we report it
+            let avail = data.active[i]
+                .force_fill(&mut self.buffer)
+                .unwrap_or_else(|e| {
+                    self.errors.borrow_mut().push(e);
+                    0
+                });
             for j in 0..avail {
                 buffer[j] += self.buffer[j];
             }
@@ -344,10 +517,13 @@ impl Source for SchedulerSource {
 
             if *start < end as u64 {
                 let len = (end - *start) as usize * self.channels as usize;
-                let avail = src.force_fill(&mut self.buffer[..len]);
+                let avail = src.force_fill(&mut self.buffer[..len]).unwrap_or_else(|e| {
+                    self.errors.borrow_mut().push(e);
+                    0
+                });
                 let dest = (*start - offset) as usize * self.channels as usize;
                 for j in 0..avail {
-                    buffer[dest + j] = self.buffer[j];
+                    buffer[dest + j] += self.buffer[j];
                 }
 
                 let (_, src) = data.scheduled.remove(i);
@@ -390,10 +566,83 @@ impl Source for SchedulerSource {
         }
 
         data.offset = end;
-        buffer.len()
+        Ok(buffer.len())
     }
 
     fn seek(&mut self, _frame: u64) -> anyhow::Result<()> {
         anyhow::bail!("cannot seek a scheduler")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Scheduler, Time};
+    use crate::source::Sine;
+    use crate::Source;
+
+    #[test]
+    fn stop_keeps_later_items() {
+        let (mut sched, mut src) = Scheduler::new(100.0, 1);
+        sched.add(0.0, Sine::new(100.0, 1, 5.0));
+        sched.mark(Time::frames(70), || ());
+
+        // stop the first sine, and start a fresh one right there
+        sched.stop(Time::frames(50));
+        sched.add(Time::frames(50), Sine::new(100.0, 1, 5.0));
+        sched.mark(Time::frames(60), || ());
+
+        let mut buffer = vec![0.0; 100];
+        assert_eq!(src.fill(&mut buffer).unwrap(), 100);
+        let mut fresh = vec![0.0; 50];
+        Sine::new(100.0, 1, 5.0).fill(&mut fresh).unwrap();
+        for (a, b) in buffer[50..].iter().zip(fresh.iter()) {
+            assert!((a - b).abs() < 0.05);
+        }
+
+        // only the mark added after the stop survives
+        let marks = src.take_marks();
+        assert_eq!(marks.len(), 1);
+        assert_eq!(marks[0].0, 60);
+    }
+
+    #[test]
+    fn skip_plays_next_item() {
+        // as a skip does: stop at a later time, and queue the next item there,
+        // before that time is rendered
+        let (mut sched, mut src) = Scheduler::new(100.0, 1);
+        sched.add(0.0, Sine::new(100.0, 1, 5.0));
+        let mut buffer = vec![0.0; 100];
+        assert_eq!(src.fill(&mut buffer).unwrap(), 100);
+
+        sched.stop(Time::frames(100));
+        sched.add(Time::frames(100), Sine::new(100.0, 1, 5.0));
+        sched.mark(Time::frames(100), || ());
+
+        assert_eq!(src.fill(&mut buffer).unwrap(), 100);
+        let mut fresh = vec![0.0; 100];
+        Sine::new(100.0, 1, 5.0).fill(&mut fresh).unwrap();
+        for (a, b) in buffer.iter().zip(fresh.iter()) {
+            assert!((a - b).abs() < 0.05);
+        }
+        assert_eq!(src.take_marks().len(), 1);
+    }
+
+    #[test]
+    fn overlapping_items_mix() {
+        // as a crossfade does: start the next item while the last still plays
+        let (mut sched, mut src) = Scheduler::new(100.0, 1);
+        sched.add(0.0, Sine::new(100.0, 1, 5.0));
+        sched.add(Time::frames(50), Sine::new(100.0, 1, 7.0));
+
+        let mut buffer = vec![0.0; 100];
+        assert_eq!(src.fill(&mut buffer).unwrap(), 100);
+        let mut first = vec![0.0; 100];
+        Sine::new(100.0, 1, 5.0).fill(&mut first).unwrap();
+        let mut second = vec![0.0; 50];
+        Sine::new(100.0, 1, 7.0).fill(&mut second).unwrap();
+        for (i, a) in buffer.iter().enumerate() {
+            let b = first[i] + if i >= 50 { second[i - 50] } else { 0.0 };
+            assert!((a - b).abs() < 0.05, "frame {}", i);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::SegmentKind;
+
+// what a station is playing, as it starts
+#[derive(Debug, Clone)]
+pub struct Segment {
+    // the station's display name
+    pub station: String,
+    pub kind: SegmentKind,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub zone: Option<String>,
+    // in seconds, if known
+    pub duration: Option<f32>,
+}
+
+impl Segment {
+    pub fn new(station: &str, kind: SegmentKind) -> Self {
+        Self {
+            station: station.to_owned(),
+            kind,
+            title: None,
+            artist: None,
+            album: None,
+            zone: None,
+            duration: None,
+        }
+    }
+
+    // a one-line description, without the station name
+    pub fn describe(&self) -> String {
+        let parts: Vec<&str> = match self.kind {
+            SegmentKind::Music => vec![&self.artist, &self.title],
+            SegmentKind::Zone => vec![&self.zone, &self.title],
+            _ => vec![&self.title],
+        }
+        .into_iter()
+        .flatten()
+        .map(|s| s.as_str())
+        .collect();
+        if parts.is_empty() {
+            self.kind.label().to_owned()
+        } else {
+            parts.join(" - ")
+        }
+    }
+}
+
+impl std::fmt::Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.station, self.describe())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::Sink;
+use crate::control::{Command, Control};
+use crate::encoder::Format;
+use crate::playlist::Entry;
+use crate::{Encoder, Segment, Sink};
 
 use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, RwLock, Weak};
@@ -9,104 +12,206 @@ use tokio_stream::StreamExt;
 
 const RADIO_TIMEOUT: Duration = Duration::from_secs(60 * 5);
 const RADIO_SAMPLERATE: u32 = 48000;
+const RADIO_CHANNELS: u16 = 2;
 const RADIO_KBITRATE: i32 = 300;
 const RADIO_QUALITY: u8 = 5;
+const RADIO_VORBIS_QUALITY: f32 = 0.6;
+const RADIO_OPUS_KBITRATE: i32 = 192;
 const RADIO_PRELOAD: usize = 128 * 1024;
+// bytes of audio between in-band metadata blocks
+const ICY_METAINT: usize = 16000;
+// metadata length is sent in 16-byte units, in a single byte,
+// so titles are cut short to leave room around them
+const ICY_MAX_TITLE: usize = 255 * 16 - "StreamTitle='';".len();
 
 struct ServerState {
     index: Arc<crate::RadioIndex>,
     running: Mutex<
         weak_table::WeakValueHashMap<String, Weak<tokio::sync::broadcast::Sender<(Bytes, Bytes)>>>,
     >,
-    metadata: RwLock<HashMap<String, String>>,
+    // station display names, and what each stream is playing now.
+    // every format of a station is its own stream, playing its own songs,
+    // so streams are keyed by station and extension, with their station.
+    names: HashMap<String, String>,
+    metadata: RwLock<HashMap<String, (String, Option<Segment>)>>,
+    // controls for running streams, and which station each one plays
+    controls: Mutex<HashMap<String, (String, Control)>>,
 }
 
 impl ServerState {
     fn new(index: crate::RadioIndex) -> Self {
+        let mut names = HashMap::new();
         let mut metadata = HashMap::new();
         for station in index.keys() {
-            metadata.insert(
+            names.insert(
                 station.clone(),
-                index.get_name(station).ok().as_deref().unwrap_or("Sprunk").to_owned(),
+                index
+                    .get_name(station)
+                    .ok()
+                    .as_deref()
+                    .unwrap_or("Sprunk")
+                    .to_owned(),
             );
+            metadata.insert(stream_key(station, &Format::Mp3), (station.clone(), None));
         }
 
         Self {
             index: Arc::new(index),
             running: Mutex::new(weak_table::WeakValueHashMap::new()),
+            names,
             metadata: RwLock::new(metadata),
+            controls: Mutex::new(HashMap::new()),
         }
     }
 
-    fn can_handle(&self, req: &hyper::Request<hyper::Body>) -> bool {
+    fn name(&self, station: &str) -> &str {
+        self.names
+            .get(station)
+            .map(|n| n.as_str())
+            .unwrap_or("Sprunk")
+    }
+
+    // the stream title, as players show it
+    fn title(&self, key: &str) -> String {
+        let stream = self.metadata.read().ok().and_then(|m| m.get(key).cloned());
+        match stream {
+            Some((_, Some(segment))) => segment.to_string(),
+            Some((station, None)) => self.name(&station).to_owned(),
+            None => "Sprunk".to_owned(),
+        }
+    }
+
+    // figure out the station and format for a request, if any
+    fn stream_for(&self, req: &hyper::Request<hyper::Body>) -> Option<(String, Format)> {
         if req.method() != hyper::Method::GET {
-            return false;
+            return None;
         }
         let mut path = req.uri().path();
         if let Some(idx) = path.rfind("/") {
             path = &path[idx + 1..];
         }
-        if !self.index.contains_key(path) {
-            return false;
+        self.stream_named(path)
+    }
+
+    // figure out the station and format for a stream name, like station.ogg
+    fn stream_named(&self, name: &str) -> Option<(String, Format)> {
+        // station names may have dots in them, so try the whole name first
+        if self.index.contains_key(name) {
+            return Some((name.to_owned(), Format::Mp3));
+        }
+        let (station, ext) = name.rsplit_once('.')?;
+        if !self.index.contains_key(station) {
+            return None;
+        }
+        Some((station.to_owned(), Format::from_name(ext)?))
+    }
+
+    // figure out the stream for a history.json or queue.json request,
+    // and whether it wants the history
+    fn playlist_for(&self, req: &hyper::Request<hyper::Body>) -> Option<(String, bool)> {
+        if req.method() != hyper::Method::GET {
+            return None;
         }
-        return true;
+        let (path, file) = req.uri().path().rsplit_once('/')?;
+        let history = match file {
+            "history.json" => true,
+            "queue.json" => false,
+            _ => return None,
+        };
+        let (station, format) = self.stream_named(path.rsplit('/').next()?)?;
+        Some((stream_key(&station, &format), history))
     }
 
     fn serve(
         self: &Arc<Self>,
         req: hyper::Request<hyper::Body>,
+        path: String,
+        format: Format,
     ) -> anyhow::Result<hyper::Response<hyper::Body>> {
-        let mut path = req.uri().path();
-        if let Some(idx) = path.rfind("/") {
-            path = &path[idx + 1..];
-        }
-        let path = path.to_owned();
+        // each format of a station is its own stream
+        let key = stream_key(&path, &format);
+
+        // does the client want in-band metadata? ogg carries its own.
+        let icy_requested = matches!(format, Format::Mp3)
+            && req
+                .headers()
+                .get("icy-metadata")
+                .map(|v| v.as_bytes() == b"1")
+                .unwrap_or(false);
+        let mut icy = if icy_requested {
+            let state = self.clone();
+            let key = key.clone();
+            Some(IcyInterleaver::new(move || state.title(&key)))
+        } else {
+            None
+        };
 
         let rx = {
             let mut running = self
                 .running
                 .lock()
                 .map_err(|_| anyhow::anyhow!("could not create station"))?;
-            if let Some(tx) = running.get(&path) {
+            if let Some(tx) = running.get(&key) {
                 tx.subscribe()
             } else {
                 let index = self.index.clone();
                 let (tx, rx) = tokio::sync::broadcast::channel(32);
                 let tx = Arc::new(tx);
-                let metadata = self
-                    .metadata
-                    .read()
-                    .map_err(|_| anyhow::anyhow!("could not read metadata"))?;
-                let reset_metadata = metadata
-                    .get(&path)
-                    .ok_or_else(|| anyhow::anyhow!("could not read station metadata"))?;
-                let output = ServerOutputStream {
-                    sender: tx.clone(),
-                    state: self.clone(),
-                    path: path.clone(),
-                    reset_metadata: reset_metadata.clone(),
-                    timeout: None,
-                    chunks: VecDeque::new(),
-                };
-                running.insert(path.clone(), tx);
+                let sender = tx.clone();
+                let (control, control_receiver) = crate::control::channel();
+                self.controls
+                    .lock()
+                    .map_err(|_| anyhow::anyhow!("could not create station"))?
+                    .insert(key.clone(), (path.clone(), control.clone()));
+                running.insert(key.clone(), tx);
+                self.metadata
+                    .write()
+                    .map_err(|_| anyhow::anyhow!("could not create station"))?
+                    .entry(key.clone())
+                    .or_insert_with(|| (path.clone(), None));
                 // this must be an honest-to-god thread, because it never yields
                 // this could be fixed in the future, but for now...
                 let state = self.clone();
+                let format = format.clone();
                 std::thread::spawn(move || {
-                    if let Ok(enc) = crate::encoder::Mp3::new(
-                        RADIO_SAMPLERATE,
-                        Some(RADIO_KBITRATE),
-                        Some(RADIO_QUALITY),
-                    ) {
-                        let sink = crate::sink::Stream::new(output, enc).realtime();
-                        let _ = index.play(path.clone(), Some(Box::new(sink)), true, move |m| {
-                            if let Ok(mut metadata) = state.metadata.write() {
-                                if let Some(v) = metadata.get_mut(&path) {
-                                    println!("{}", m);
-                                    *v = m;
+                    if let Ok(encoder) = Self::encoder(&format) {
+                        let sink = ServerOutput {
+                            sender,
+                            state: state.clone(),
+                            key: key.clone(),
+                            timeout: None,
+                            header: Bytes::copy_from_slice(encoder.header()),
+                            chunks: VecDeque::new(),
+                            unsent: 0,
+                            encoder,
+                        }
+                        .realtime();
+                        let metadata_state = state.clone();
+                        let metadata_key = key.clone();
+                        let _ = index.play(
+                            path.clone(),
+                            Some(Box::new(sink)),
+                            true,
+                            Some(control_receiver),
+                            move |m| {
+                                if let Ok(mut metadata) = metadata_state.metadata.write() {
+                                    if let Some(v) = metadata.get_mut(&metadata_key) {
+                                        println!("{}", m);
+                                        v.1 = Some(m);
+                                    }
                                 }
-                            }
-                        });
+                            },
+                        );
+                    }
+                    // forget our control, unless a new stream replaced it
+                    if let Ok(mut controls) = state.controls.lock() {
+                        if controls
+                            .get(&key)
+                            .map(|(_, c)| c.same_station(&control))
+                            .unwrap_or(false)
+                        {
+                            controls.remove(&key);
+                        }
                     }
                 });
                 rx
@@ -118,16 +223,168 @@ impl ServerState {
             .map(move |r| {
                 let r = r.map_err(|_| anyhow::anyhow!("end of stream"));
                 counter += 1;
-                if counter == 1 {
+                let r = if counter == 1 {
                     r.map(|t| t.0)
                 } else {
                     r.map(|t| t.1)
+                };
+                if let Some(ref mut icy) = icy {
+                    r.map(|chunk| icy.interleave(&chunk))
+                } else {
+                    r
                 }
             });
         let mut response = hyper::Response::new(hyper::Body::wrap_stream(body));
         response
             .headers_mut()
-            .insert(hyper::header::CONTENT_TYPE, "audio/mpeg".parse()?);
+            .insert(hyper::header::CONTENT_TYPE, format.content_type().parse()?);
+        if icy_requested {
+            response
+                .headers_mut()
+                .insert("icy-metaint", ICY_METAINT.into());
+        }
+        Ok(response)
+    }
+
+    // figure out the station and command for a control request, if any
+    fn control_for(&self, req: &hyper::Request<hyper::Body>) -> Option<(String, String)> {
+        if req.method() != hyper::Method::POST {
+            return None;
+        }
+        let (path, command) = req.uri().path().rsplit_once('/')?;
+        let station = path.rsplit('/').next()?;
+        if !self.index.contains_key(station) {
+            return None;
+        }
+        Some((station.to_owned(), command.to_owned()))
+    }
+
+    fn authorized(&self, req: &hyper::Request<hyper::Body>, station: &str) -> bool {
+        let Some(auth) = self.index.control_auth(station) else {
+            return false;
+        };
+        // basic auth, user:password in base64
+        let credentials = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD
+                    .decode(v.trim())
+                    .ok()
+            });
+        credentials.is_some_and(|c| auth.check(&c))
+    }
+
+    async fn control(
+        self: &Arc<Self>,
+        req: hyper::Request<hyper::Body>,
+        station: String,
+        command: String,
+    ) -> anyhow::Result<hyper::Response<hyper::Body>> {
+        use hyper::StatusCode;
+
+        if self.index.control_auth(&station).is_none() {
+            return text_response(StatusCode::FORBIDDEN, "station cannot be controlled");
+        }
+        if !self.authorized(&req, &station) {
+            let mut response = text_response(StatusCode::UNAUTHORIZED, "unauthorized")?;
+            response.headers_mut().insert(
+                hyper::header::WWW_AUTHENTICATE,
+                "Basic realm=\"sprunk\"".parse()?,
+            );
+            return Ok(response);
+        }
+
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        let command = match Command::parse(&command, &String::from_utf8_lossy(&body)) {
+            Ok(command) => command,
+            Err(e) => return text_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+
+        // every format of a station plays separately, so tell them all
+        let controls: Vec<Control> = self
+            .controls
+            .lock()
+            .map_err(|_| anyhow::anyhow!("could not read controls"))?
+            .values()
+            .filter(|(s, _)| *s == station)
+            .map(|(_, c)| c.clone())
+            .collect();
+        if controls.is_empty() {
+            return text_response(StatusCode::CONFLICT, "station is not running");
+        }
+        // and don't stop partway, or they'd drift apart
+        let mut errors: Vec<String> = vec![];
+        for control in controls {
+            if let Err(e) = control.send(command.clone()).await {
+                if !errors.contains(&e.to_string()) {
+                    errors.push(e.to_string());
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return text_response(StatusCode::BAD_REQUEST, &errors.join("\n"));
+        }
+        text_response(StatusCode::OK, "ok")
+    }
+
+    fn encoder(format: &Format) -> anyhow::Result<Box<dyn Encoder>> {
+        Ok(match format {
+            Format::Mp3 => Box::new(crate::encoder::Mp3::new(
+                RADIO_SAMPLERATE,
+                RADIO_CHANNELS,
+                Some(RADIO_KBITRATE),
+                Some(RADIO_QUALITY),
+            )?),
+            Format::Vorbis => Box::new(crate::encoder::Vorbis::new(
+                RADIO_SAMPLERATE,
+                RADIO_CHANNELS,
+                Some(RADIO_VORBIS_QUALITY),
+            )?),
+            Format::Opus => Box::new(crate::encoder::Opus::new(
+                RADIO_SAMPLERATE,
+                RADIO_CHANNELS,
+                Some(RADIO_OPUS_KBITRATE),
+            )?),
+            _ => format.encoder(RADIO_SAMPLERATE, RADIO_CHANNELS)?,
+        })
+    }
+
+    fn playlist_json(
+        &self,
+        key: &str,
+        history: bool,
+    ) -> anyhow::Result<hyper::Response<hyper::Body>> {
+        // streams that aren't running have nothing to show
+        let entries = self
+            .controls
+            .lock()
+            .map_err(|_| anyhow::anyhow!("could not read controls"))?
+            .get(key)
+            .map(|(_, control)| {
+                if history {
+                    control.playlist().history()
+                } else {
+                    control.playlist().queue()
+                }
+            })
+            .unwrap_or_default();
+
+        let body = format!(
+            "[{}]",
+            entries
+                .iter()
+                .map(entry_json)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let mut response = hyper::Response::new(hyper::Body::from(body));
+        response
+            .headers_mut()
+            .insert(hyper::header::CONTENT_TYPE, "application/json".parse()?);
         Ok(response)
     }
 
@@ -143,17 +400,31 @@ impl ServerState {
             .metadata
             .read()
             .map_err(|_| anyhow::anyhow!("could not read metadata"))?;
-        for (station, title) in metadata.iter() {
-            // FIXME json escaping
+        for (key, (station, segment)) in metadata.iter() {
             if !first {
                 body += ", ";
             }
             first = false;
-            body += "{\"listenurl\": \"./";
-            body += station;
-            body += "\", \"title\": \"";
-            body += title;
-            body += "\"}";
+            // mp3 streams are the default, and are listened to without an extension
+            let url = if *key == stream_key(station, &Format::Mp3) {
+                station
+            } else {
+                key
+            };
+            body += "{\"listenurl\": ";
+            body += &json_string(&format!("./{}", url));
+            body += ", \"server_name\": ";
+            body += &json_string(self.name(station));
+            body += ", \"title\": ";
+            body += &json_string(&match segment {
+                Some(segment) => segment.to_string(),
+                None => self.name(station).to_owned(),
+            });
+            if let Some(segment) = segment {
+                body += ", \"segment\": ";
+                body += &segment_json(segment);
+            }
+            body += "}";
         }
         body += "]";
         if icecast {
@@ -168,36 +439,186 @@ impl ServerState {
     }
 }
 
-struct ServerOutputStream {
+// each format of a station plays separately, under this name
+fn stream_key(station: &str, format: &Format) -> String {
+    format!("{}.{}", station, format.extension())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_option(s: Option<&str>) -> String {
+    s.map(json_string).unwrap_or_else(|| "null".to_owned())
+}
+
+fn segment_json(segment: &Segment) -> String {
+    let duration = segment
+        .duration
+        .map(|d| format!("{:.3}", d))
+        .unwrap_or_else(|| "null".to_owned());
+    format!(
+        "{{\"kind\": {}, \"title\": {}, \"artist\": {}, \"album\": {}, \"zone\": {}, \"duration\": {}}}",
+        json_string(segment.kind.name()),
+        json_option(segment.title.as_deref()),
+        json_option(segment.artist.as_deref()),
+        json_option(segment.album.as_deref()),
+        json_option(segment.zone.as_deref()),
+        duration
+    )
+}
+
+fn entry_json(entry: &Entry) -> String {
+    let start = entry
+        .timestamp()
+        .map(|t| format!("{:.3}", t))
+        .unwrap_or_else(|| "null".to_owned());
+    format!(
+        "{{\"segment\": {}, \"start\": {}}}",
+        segment_json(&entry.segment),
+        start
+    )
+}
+
+fn text_response(
+    status: hyper::StatusCode,
+    message: &str,
+) -> anyhow::Result<hyper::Response<hyper::Body>> {
+    let mut response = hyper::Response::new(hyper::Body::from(format!("{}\n", message)));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, "text/plain".parse()?);
+    Ok(response)
+}
+
+// inserts a metadata block after every ICY_METAINT bytes of audio
+struct IcyInterleaver {
+    // what the stream is playing now
+    title: Box<dyn Fn() -> String + Send>,
+    remaining: usize,
+    last_title: Option<String>,
+}
+
+impl IcyInterleaver {
+    fn new<F>(title: F) -> Self
+    where
+        F: Fn() -> String + Send + 'static,
+    {
+        Self {
+            title: Box::new(title),
+            remaining: ICY_METAINT,
+            last_title: None,
+        }
+    }
+
+    fn interleave(&mut self, mut chunk: &[u8]) -> Bytes {
+        let mut out = Vec::with_capacity(chunk.len() + 1);
+        while chunk.len() >= self.remaining {
+            out.extend_from_slice(&chunk[..self.remaining]);
+            chunk = &chunk[self.remaining..];
+            self.write_metadata(&mut out);
+            self.remaining = ICY_METAINT;
+        }
+        out.extend_from_slice(chunk);
+        self.remaining -= chunk.len();
+        out.into()
+    }
+
+    fn write_metadata(&mut self, out: &mut Vec<u8>) {
+        let title = (self.title)();
+
+        // only send the title when it changes, otherwise an empty block
+        if self.last_title.as_ref() == Some(&title) {
+            out.push(0);
+            return;
+        }
+
+        // players read up to the closing '; so keep that out of the title
+        let mut text = title.clone();
+        while text.contains("';") {
+            text = text.replace("';", "'");
+        }
+        if text.len() > ICY_MAX_TITLE {
+            let mut end = ICY_MAX_TITLE;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+        let mut block = format!("StreamTitle='{}';", text).into_bytes();
+        let units = block.len().div_ceil(16);
+        block.resize(units * 16, 0);
+        out.push(units as u8);
+        out.extend_from_slice(&block);
+        self.last_title = Some(title);
+    }
+}
+
+struct ServerOutput {
     sender: Arc<tokio::sync::broadcast::Sender<(Bytes, Bytes)>>,
     state: Arc<ServerState>,
-    path: String,
-    reset_metadata: String,
+    // which stream this is, as in the metadata
+    key: String,
     timeout: Option<Instant>,
-    chunks: VecDeque<Bytes>,
+    encoder: Box<dyn Encoder>,
+    // stream headers, sent ahead of the preload to new listeners
+    header: Bytes,
+    // recent chunks, and how many frames went into each
+    chunks: VecDeque<(Bytes, u64)>,
+    // frames encoded that have not come out in a chunk yet
+    unsent: u64,
 }
 
-impl std::io::Write for ServerOutputStream {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+impl Sink for ServerOutput {
+    fn samplerate(&self) -> f32 {
+        self.encoder.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.encoder.channels()
+    }
+
+    fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()> {
         if let Some(timeout) = self.timeout {
             if Instant::now() > timeout {
                 if let Ok(mut metadata) = self.state.metadata.write() {
-                    metadata.insert(self.path.clone(), self.reset_metadata.clone());
+                    if let Some(v) = metadata.get_mut(&self.key) {
+                        v.1 = None;
+                    }
                 }
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::BrokenPipe,
-                    "radio timed out",
-                ));
+                anyhow::bail!("radio timed out");
             }
         }
 
-        let chunk = Bytes::copy_from_slice(buf);
-        self.chunks.push_back(chunk.clone());
-        while self.chunks.iter().map(|c| c.len()).sum::<usize>() > RADIO_PRELOAD {
+        self.unsent += (buffer.len() / self.channels() as usize) as u64;
+        let encoded = self.encoder.encode(buffer)?;
+        if encoded.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = Bytes::copy_from_slice(encoded);
+        self.chunks.push_back((chunk.clone(), self.unsent));
+        self.unsent = 0;
+        while self.chunks.iter().map(|c| c.0.len()).sum::<usize>() > RADIO_PRELOAD {
             self.chunks.pop_front();
         }
-        let mut preload = Vec::with_capacity(self.chunks.iter().map(|c| c.len()).sum());
-        for c in self.chunks.iter() {
+        let mut preload = Vec::with_capacity(
+            self.header.len() + self.chunks.iter().map(|c| c.0.len()).sum::<usize>(),
+        );
+        preload.extend_from_slice(&self.header);
+        for (c, _) in self.chunks.iter() {
             preload.extend_from_slice(&c);
         }
 
@@ -209,11 +630,13 @@ impl std::io::Write for ServerOutputStream {
             self.timeout = None;
         }
 
-        Ok(buf.len())
+        Ok(())
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    fn latency(&self) -> f32 {
+        // new listeners start at the beginning of the preload
+        let frames: u64 = self.chunks.iter().map(|c| c.1).sum();
+        frames as f32 / self.samplerate() + self.encoder.latency()
     }
 }
 
@@ -236,8 +659,12 @@ where
                 let state = state.clone();
                 let static_ = static_.clone();
                 async move {
-                    if state.can_handle(&req) {
-                        state.serve(req)
+                    if let Some((station, format)) = state.stream_for(&req) {
+                        state.serve(req, station, format)
+                    } else if let Some((station, command)) = state.control_for(&req) {
+                        state.control(req, station, command).await
+                    } else if let Some((key, history)) = state.playlist_for(&req) {
+                        state.playlist_json(&key, history)
                     } else if req.method() == &hyper::Method::GET
                         && req.uri().path().ends_with("/status-json.xsl")
                     {
@@ -258,3 +685,73 @@ where
 
     Ok(server.await?)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{IcyInterleaver, ICY_METAINT};
+    use std::sync::{Arc, Mutex};
+
+    // split interleaved output back into audio and metadata blocks
+    fn split(mut data: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let (mut audio, mut blocks) = (vec![], vec![]);
+        while data.len() > ICY_METAINT {
+            audio.extend_from_slice(&data[..ICY_METAINT]);
+            let len = data[ICY_METAINT] as usize * 16;
+            blocks.push(data[ICY_METAINT + 1..ICY_METAINT + 1 + len].to_vec());
+            data = &data[ICY_METAINT + 1 + len..];
+        }
+        audio.extend_from_slice(data);
+        (audio, blocks)
+    }
+
+    #[test]
+    fn metadata_every_metaint() {
+        let title = Arc::new(Mutex::new("First".to_owned()));
+        let shared = title.clone();
+        let mut icy = IcyInterleaver::new(move || shared.lock().unwrap().clone());
+
+        // chunks that don't line up with the metadata interval
+        let audio: Vec<u8> = (0..ICY_METAINT * 4 + 123).map(|i| i as u8).collect();
+        let mut out = vec![];
+        for (i, chunk) in audio.chunks(7001).enumerate() {
+            if i == 5 {
+                *title.lock().unwrap() = "Second".to_owned();
+            }
+            out.extend_from_slice(&icy.interleave(chunk));
+        }
+
+        let (heard, blocks) = split(&out);
+        assert_eq!(heard, audio);
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[0].starts_with(b"StreamTitle='First';"));
+        // unchanged titles are empty blocks
+        assert!(blocks[1].is_empty());
+        assert!(blocks[2].starts_with(b"StreamTitle='Second';"));
+        assert!(blocks[3].is_empty());
+    }
+
+    #[test]
+    fn long_titles_keep_terminator() {
+        for title in ["x".repeat(5000), "\u{e9}".repeat(3000), "';".repeat(3000)] {
+            let mut icy = IcyInterleaver::new(move || title.clone());
+            let out = icy.interleave(&vec![0; ICY_METAINT]);
+            let units = out[ICY_METAINT] as usize;
+            assert!(units > 0 && units <= 255);
+            let block = &out[ICY_METAINT + 1..];
+            assert_eq!(block.len(), units * 16);
+            let text = std::str::from_utf8(block).unwrap().trim_end_matches('\0');
+            assert!(text.starts_with("StreamTitle='"));
+            assert!(text.ends_with("';"));
+            assert_eq!(text.matches("';").count(), 1);
+        }
+    }
+
+    #[test]
+    fn control_auth_needs_exact_credentials() {
+        let auth = crate::ControlAuth::new("admin", "hackme");
+        assert!(auth.check(b"admin:hackme"));
+        for wrong in ["", "admin:", "admin:hackm", "admin:hackme!", "admin:hackmf"] {
+            assert!(!auth.check(wrong.as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use super::{Sink, Stream};
+use crate::Encoder;
+
+// like Stream, but can go back and fix up the file header when done
+pub struct File<E>
+where
+    E: Encoder,
+{
+    stream: Stream<std::io::BufWriter<std::fs::File>, E>,
+    finished: bool,
+}
+
+impl<E> File<E>
+where
+    E: Encoder,
+{
+    pub fn create<P>(path: P, encoder: E) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        Ok(File {
+            stream: Stream::new(file, encoder),
+            finished: false,
+        })
+    }
+}
+
+impl<E> Sink for File<E>
+where
+    E: Encoder,
+{
+    fn samplerate(&self) -> f32 {
+        self.stream.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.stream.channels()
+    }
+
+    fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()> {
+        self.stream.write(buffer)
+    }
+
+    fn latency(&self) -> f32 {
+        self.stream.latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.stream.finish()?;
+
+        if let Some(header) = self.stream.encoder().rewrite_header() {
+            let header = header.to_owned();
+            let file = self.stream.inner_mut();
+            file.seek(std::io::SeekFrom::Start(0))?;
+            file.write_all(&header)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<E> Drop for File<E>
+where
+    E: Encoder,
+{
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::File;
+    use crate::encoder::{Flac, Wav};
+    use crate::source::Media;
+    use crate::{Encoder, Sink, Source};
+
+    // write a known buffer through a file, and read it back
+    fn round_trip<E>(name: &str, encoder: E)
+    where
+        E: Encoder,
+    {
+        let path = std::env::temp_dir().join(format!("sprunk-{}-{}", std::process::id(), name));
+        let (samplerate, channels) = (encoder.samplerate(), encoder.channels());
+        let frames = 10007;
+        let input: Vec<f32> = (0..frames * channels as usize)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect();
+
+        let mut file = File::create(&path, encoder).unwrap();
+        for chunk in input.chunks(1234) {
+            file.write(chunk).unwrap();
+        }
+        file.finish().unwrap();
+        drop(file);
+
+        let mut media = Media::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(media.samplerate(), samplerate, "{}", name);
+        assert_eq!(media.channels(), channels, "{}", name);
+        assert_eq!(media.len(), Some(frames as u64), "{}", name);
+        let mut output = vec![0.0; input.len() + 100];
+        assert_eq!(
+            media.force_fill(&mut output).unwrap(),
+            input.len(),
+            "{}",
+            name
+        );
+        for (a, b) in input.iter().zip(output.iter()) {
+            assert!((a - b).abs() < 0.001, "{}", name);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rewrites_headers() {
+        round_trip("test.wav", Wav::new(22050, 2).unwrap());
+        round_trip("test.flac", Flac::new(22050, 2).unwrap());
+    }
+}
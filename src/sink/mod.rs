@@ -1,10 +1,12 @@
+mod file;
 mod realtime;
 mod shout;
 mod stream;
 mod system;
 
-pub use realtime::Realtime;
 pub use self::shout::Shout;
+pub use file::File;
+pub use realtime::Realtime;
 pub use stream::Stream;
 pub use system::System;
 
@@ -14,6 +16,16 @@ pub trait Sink {
 
     fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()>;
 
+    // seconds between audio being written and being heard
+    fn latency(&self) -> f32 {
+        0.0
+    }
+
+    // called once at the end of the stream, to flush anything buffered
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     fn realtime(self) -> Realtime<Self>
     where
         Self: Sized,
@@ -34,4 +46,12 @@ impl Sink for Box<dyn Sink> {
     fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()> {
         (**self).write(buffer)
     }
+
+    fn latency(&self) -> f32 {
+        (**self).latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        (**self).finish()
+    }
 }
@@ -44,4 +44,17 @@ where
         *self.runout.get_or_insert(now) += duration;
         Ok(())
     }
+
+    fn latency(&self) -> f32 {
+        // everything written that hasn't run out yet is still to be heard
+        let ahead = self
+            .runout
+            .map(|r| r.saturating_duration_since(Instant::now()).as_secs_f32())
+            .unwrap_or(0.0);
+        ahead + self.inner.latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.inner.finish()
+    }
 }
@@ -4,6 +4,8 @@ use crate::Encoder;
 pub struct Shout<E> {
     conn: shout::ShoutConn,
     encoder: E,
+    started: bool,
+    finished: bool,
 }
 
 impl<E> Shout<E>
@@ -27,16 +29,21 @@ where
             builder = builder.password(pw.to_owned());
         }
 
-        // FIXME check with encoder...
         builder = builder.format(match encoder.format() {
             Format::Mp3 => shout::ShoutFormat::MP3,
+            Format::Vorbis | Format::Opus => shout::ShoutFormat::Ogg,
             _ => anyhow::bail!("cannot stream in this format"),
         });
 
         let conn = builder
             .build()
             .map_err(|_| anyhow::anyhow!("error connecting to streaming server"))?;
-        Ok(Shout { conn, encoder })
+        Ok(Shout {
+            conn,
+            encoder,
+            started: false,
+            finished: false,
+        })
     }
 }
 
@@ -53,6 +60,12 @@ where
     }
 
     fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()> {
+        if !self.started && !self.encoder.header().is_empty() {
+            self.conn
+                .send(self.encoder.header())
+                .map_err(|_| anyhow::anyhow!("error sending data to streaming server"))?;
+        }
+        self.started = true;
         let encoded = self.encoder.encode(buffer)?;
         self.conn
             .send(encoded)
@@ -60,4 +73,21 @@ where
         self.conn.sync();
         Ok(())
     }
+
+    fn latency(&self) -> f32 {
+        self.encoder.latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let encoded = self.encoder.finish()?;
+        self.conn
+            .send(encoded)
+            .map_err(|_| anyhow::anyhow!("error sending data to streaming server"))?;
+        self.conn.sync();
+        Ok(())
+    }
 }
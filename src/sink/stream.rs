@@ -1,8 +1,14 @@
 use crate::Encoder;
 
-pub struct Stream<F, E> {
+pub struct Stream<F, E>
+where
+    F: std::io::Write,
+    E: Encoder,
+{
     inner: F,
     encoder: E,
+    started: bool,
+    finished: bool,
 }
 
 impl<F, E> Stream<F, E>
@@ -11,7 +17,20 @@ where
     E: Encoder,
 {
     pub fn new(inner: F, encoder: E) -> Self {
-        Stream { inner, encoder }
+        Stream {
+            inner,
+            encoder,
+            started: false,
+            finished: false,
+        }
+    }
+
+    pub(super) fn inner_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    pub(super) fn encoder(&self) -> &E {
+        &self.encoder
     }
 }
 
@@ -29,8 +48,42 @@ where
     }
 
     fn write(&mut self, buffer: &[f32]) -> anyhow::Result<()> {
+        if !self.started {
+            self.inner.write_all(self.encoder.header())?;
+            self.started = true;
+        }
         let encoded = self.encoder.encode(buffer)?;
         self.inner.write_all(encoded)?;
         Ok(())
     }
+
+    fn latency(&self) -> f32 {
+        self.encoder.latency()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if !self.started {
+            self.inner.write_all(self.encoder.header())?;
+            self.started = true;
+        }
+        let encoded = self.encoder.finish()?;
+        self.inner.write_all(encoded)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+impl<F, E> Drop for Stream<F, E>
+where
+    F: std::io::Write,
+    E: Encoder,
+{
+    fn drop(&mut self) {
+        // nowhere to report errors, but at least try
+        let _ = super::Sink::finish(self);
+    }
 }
@@ -1,11 +1,11 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{OutputCallbackInfo, Sample, SampleFormat, Stream, StreamConfig};
-use rb::{RbConsumer, RbProducer, RB};
+use rb::{RbConsumer, RbInspector, RbProducer, RB};
 
 pub struct System {
     config: StreamConfig,
     stream: Option<Stream>,
-    _buffer: rb::SpscRb<f32>,
+    buffer: rb::SpscRb<f32>,
     tx: rb::Producer<f32>,
 }
 
@@ -65,7 +65,7 @@ impl System {
         Ok(Self {
             config,
             stream: Some(stream),
-            _buffer: buffer,
+            buffer,
             tx,
         })
     }
@@ -111,4 +111,10 @@ impl super::Sink for System {
         }
         Ok(())
     }
+
+    fn latency(&self) -> f32 {
+        // whatever is still in the ring buffer has yet to be played
+        let frames = self.buffer.count() / self.config.channels as usize;
+        frames as f32 / self.samplerate()
+    }
 }
@@ -1,47 +1,194 @@
-use crate::{source, Scheduler, Source, Time};
+use crate::{source, AnalysisCache, Scheduler, Source, Time};
 
+use std::cell::Cell;
 use std::path::PathBuf;
+use std::rc::Rc;
+
+// how long before an item is needed to schedule it, in seconds
+const LOOKAHEAD: f32 = 5.0;
+
+// how quickly to fade out skipped items, in seconds
+const SKIP_FADE: f32 = 0.3;
 
 pub struct SoftScheduler {
     padding: f32,
-    over_volume: f32,
+    // main ducks under over, shared with the mix that does it
+    ducking: Rc<Cell<source::Ducking>>,
     loudness: f32,
+    cache: AnalysisCache,
+    crossfade: Option<f32>,
+    crossfade_forced: bool,
+    // silence threshold in dBFS, for trimming items
+    trim: Option<f32>,
+    trim_forced: bool,
+    // can the last thing we added be crossfaded into the next?
+    fade_out: bool,
     soft: Time,
     hard: Time,
     main: Scheduler,
+    // when the last item started, and how to fade it out
+    last: Option<(Time, source::Fader)>,
     over: Scheduler,
+    root: Scheduler,
 }
 
 impl SoftScheduler {
-    pub fn new(root: &mut Scheduler, padding: f32, over_volume: f32, loudness: f32) -> Self {
+    pub fn new(
+        mut root: Scheduler,
+        padding: f32,
+        loudness: f32,
+        cache: AnalysisCache,
+    ) -> Self {
+        // voiceovers play over main, which dips under them
+        let ducking = Rc::new(Cell::new(source::Ducking::default()));
+        let (main, main_src) = root.detached_subscheduler(1.0);
+        let (over, over_src) = root.detached_subscheduler(1.0);
+        root.attach(source::Duck::new(main_src, over_src, ducking.clone()));
         Self {
             padding,
-            over_volume,
+            ducking,
             loudness,
+            cache,
+            crossfade: None,
+            crossfade_forced: false,
+            trim: None,
+            trim_forced: false,
+            fade_out: false,
             soft: Time::seconds(0.0),
             hard: Time::seconds(0.0),
-            main: root.subscheduler(),
-            over: root.subscheduler(),
+            main,
+            last: None,
+            over,
+            root,
+        }
+    }
+
+    pub fn set_ducking(&mut self, ducking: source::Ducking) {
+        self.ducking.set(ducking);
+    }
+
+    // overlap consecutive items by this many seconds, or None to disable.
+    // forced items (ads, news) only crossfade if forced is true.
+    pub fn set_crossfade(&mut self, crossfade: Option<f32>, forced: bool) {
+        self.crossfade = crossfade.filter(|c| *c > 0.0);
+        self.crossfade_forced = forced;
+    }
+
+    // cut silence quieter than threshold dBFS off items, or None to disable.
+    // forced items (ads, news) are only trimmed if forced is true.
+    pub fn set_trim(&mut self, threshold: Option<f32>, forced: bool) {
+        self.trim = threshold;
+        self.trim_forced = forced;
+    }
+
+    // open and normalize a file, trimmed if asked. also returns how many
+    // seconds were trimmed off the start.
+    fn open(
+        &self,
+        path: &PathBuf,
+        trim: bool,
+    ) -> anyhow::Result<(source::Volume<source::Trim<source::Media>>, f32)> {
+        let media = source::Media::new(std::fs::File::open(path)?)?;
+        let media = match self.trim.filter(|_| trim) {
+            Some(threshold) => media.trim_cached(threshold, &self.cache, path)?,
+            None => source::Trim::whole(media),
+        };
+        let offset = media.offset() as f32 / media.samplerate();
+        Ok((media.normalize_cached(self.loudness, &self.cache, path), offset))
+    }
+
+    // when it's nearly time for the next item to be scheduled
+    pub fn ready_time(&self) -> Time {
+        let samplerate = self.main.samplerate();
+        let mut earliest = self.soft.to_seconds(samplerate);
+        if let Some(crossfade) = self.crossfade.filter(|_| self.fade_out) {
+            earliest = earliest.min(self.fade_start(crossfade).to_seconds(samplerate));
+        }
+        Time::seconds((earliest - LOOKAHEAD).max(0.0))
+    }
+
+    // when the last item starts fading out, but never before it starts.
+    // in frames, like the times it's compared against.
+    fn fade_start(&self, crossfade: f32) -> Time {
+        let samplerate = self.main.samplerate();
+        let last_start = self.last.as_ref().map(|l| l.0.to_seconds(samplerate)).unwrap_or(0.0);
+        let fade_start = self.hard.to_seconds(samplerate) - (self.padding + crossfade);
+        Time::frames(Time::seconds(fade_start.max(last_start)).to_frames(samplerate))
+    }
+
+    pub fn samplerate(&self) -> f32 {
+        self.main.samplerate()
+    }
+
+    pub fn system_time(&self, time: Time) -> std::time::SystemTime {
+        self.main.system_time(time)
+    }
+
+    pub async fn wait(&mut self, time: Time) -> anyhow::Result<()> {
+        self.main.wait(time).await?;
+        Ok(())
+    }
+
+    // call f once this time is heard, unless skipped first
+    pub fn mark<F>(&mut self, time: Time, f: F)
+    where
+        F: FnOnce() + 'static,
+    {
+        self.main.mark(time, f);
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.root.set_paused(paused);
+    }
+
+    // cut off everything playing or scheduled, and start over from now
+    pub fn skip(&mut self) {
+        let now = self.main.now();
+        let end = now + SKIP_FADE;
+
+        for sched in [&mut self.main, &mut self.over] {
+            sched.cancel_volume(now);
+            sched.set_volume(now, 0.0, SKIP_FADE);
+            sched.stop(end);
+            sched.set_volume(end, 1.0, 0.0);
         }
+
+        self.last = None;
+        self.soft = end;
+        self.hard = end;
+        self.fade_out = false;
     }
-    pub async fn add<'a>(
+
+    // schedule an item, and return when it starts and ends
+    pub fn add(
         &mut self,
         mainpath: &PathBuf,
         overpath: Option<&PathBuf>,
         pre: f32,
         post: Option<f32>,
         force: bool,
-    ) -> anyhow::Result<()> {
-        let main = source::Media::new(std::fs::File::open(&mainpath)?)?.normalize(self.loudness);
+    ) -> anyhow::Result<(Time, Time)> {
+        let (main, offset) = self.open(mainpath, !force || self.trim_forced)?;
+        // cue points are from the start of the file, not the trimmed start
+        let pre = (pre - offset).max(0.0);
+        let post = post.map(|p| (p - offset).max(0.0));
         let mut start = self.hard;
 
+        // are we crossfading from the last item?
+        let fades = self.crossfade.is_some() && (!force || self.crossfade_forced);
+        let crossfade = self.crossfade.filter(|_| fades && self.fade_out);
+        if let Some(crossfade) = crossfade {
+            // start this one under the old item as it ends
+            start = self.fade_start(crossfade);
+        }
+
         // do we have a voiceover to do?
         if let Some(overpath) = overpath {
-            let over =
-                source::Media::new(std::fs::File::open(overpath)?)?.normalize(self.loudness);
+            let (over, _) = self.open(overpath, true)?;
             // figure out when our soft time ends, and how long it is
             let mut soft_end = start + pre;
-            let soft_amt = (soft_end - self.soft).to_seconds(self.over.samplerate());
+            let samplerate = self.over.samplerate();
+            let soft_amt = soft_end.to_seconds(samplerate) - self.soft.to_seconds(samplerate);
             if let Some(over_frames) = over.len() {
                 // we have a voiceover with a known length. will it fit?
                 let over_amt = over_frames as f32 / over.samplerate() + 2.0 * self.padding;
@@ -53,29 +200,88 @@ impl SoftScheduler {
                         start = start + bonus;
                         soft_end = soft_end + bonus;
                     }
-                    // schedule the voiceover and volume ramps
+                    // schedule the voiceover, and main ducks under it
                     let over_start = soft_end - over_amt;
-                    self.main
-                        .set_volume(over_start, self.over_volume, self.padding);
-                    self.main
-                        .set_volume(soft_end - self.padding, 1.0, self.padding);
                     self.over.add(over_start + self.padding, over);
                 }
             }
         }
 
+        // fade out the old item from wherever this one ended up starting
+        if let (Some(crossfade), Some((last_start, fader))) = (crossfade, &self.last) {
+            let samplerate = self.main.samplerate();
+            fader.fade_out_at(
+                start.to_seconds(samplerate) - last_start.to_seconds(samplerate),
+                crossfade,
+                source::Curve::EqualPower,
+            );
+        }
+
+        // fade in the new item, and keep hold of it to fade out later
+        let main = main.envelope();
+        if let Some(crossfade) = crossfade {
+            main.fader().fade_in(crossfade, source::Curve::EqualPower);
+        }
+        self.last = Some((start, main.fader()));
+
         // schedule the main showpiece
         let end = self
             .main
             .add(start, main)
             .ok_or_else(|| anyhow::anyhow!("unknown sound file length"))?;
 
-        // wait until the start
-        self.main.wait(start).await?;
-
         // update our soft and hard start times
         self.soft = if let Some(p) = post { start + p } else { end };
         self.hard = end + self.padding;
-        Ok(())
+        self.fade_out = fades;
+        Ok((start, end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SoftScheduler;
+    use crate::{AnalysisCache, Scheduler, Source};
+
+    const SAMPLERATE: u32 = 8000;
+
+    // seconds of steady noise, written out to a temporary file
+    fn noise_file(name: &str, seconds: u32) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("sprunk-{}-{}.wav", name, std::process::id()));
+        let samples =
+            (0..SAMPLERATE * seconds).map(|i| ((i.wrapping_mul(2654435761) >> 16) as i16) / 4);
+        std::fs::write(&path, crate::source::test_wav(SAMPLERATE, 1, samples)).unwrap();
+        path
+    }
+
+    fn rms(buffer: &[f32]) -> f32 {
+        (buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn crossfade_waits_for_voiceover() {
+        let (root, mut src) = Scheduler::new(SAMPLERATE as f32, 1);
+        let mut soft = SoftScheduler::new(root, 0.0, -14.0, AnalysisCache::new());
+        soft.set_crossfade(Some(0.5), true);
+        let song = noise_file("crossfade-song", 2);
+        let intro = noise_file("crossfade-intro", 1);
+
+        // a hard start, then a crossfade into a song whose intro doesn't fit,
+        // so the song is pushed back until after the intro
+        soft.add(&song, None, 0.0, None, false).unwrap();
+        let (start, _) = soft.add(&song, Some(&intro), 0.0, None, true).unwrap();
+        let samplerate = SAMPLERATE as f32;
+        assert_eq!(start.to_frames(samplerate), 3 * SAMPLERATE as u64);
+
+        // so the first song plays out in full, not fading out for nothing
+        let mut buffer = vec![0.0; 2 * SAMPLERATE as usize];
+        assert_eq!(src.fill(&mut buffer).unwrap(), buffer.len());
+        let at = |seconds: f32| (seconds * samplerate) as usize;
+        let steady = rms(&buffer[at(0.5)..at(1.0)]);
+        let tail = rms(&buffer[at(1.7)..at(1.95)]);
+        assert!(tail > steady * 0.9, "tail {} against {}", tail, steady);
+
+        std::fs::remove_file(&song).unwrap();
+        std::fs::remove_file(&intro).unwrap();
     }
 }
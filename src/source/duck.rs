@@ -0,0 +1,161 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// how quickly the key level falls away between words, in seconds
+const DETECT_RELEASE: f32 = 0.05;
+
+// how to duck one signal under another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ducking {
+    // key level that starts ducking, in dBFS
+    pub threshold: f32,
+    // how far to duck, in dB
+    pub depth: f32,
+    // how long to duck and recover, in seconds
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            threshold: -40.0,
+            depth: 6.0,
+            attack: 0.05,
+            release: 0.6,
+        }
+    }
+}
+
+// mixes key over main, ducking main while key is loud
+pub struct Duck<M, K> {
+    main: M,
+    key: K,
+    // shared, so it can be changed while playing
+    settings: Rc<Cell<Ducking>>,
+    level: f32,
+    gain: f32,
+    buffer: Vec<f32>,
+}
+
+// smoothing coefficient for a one-pole filter with this time constant
+fn coefficient(seconds: f32, samplerate: f32) -> f32 {
+    if seconds <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (seconds * samplerate)).exp()
+    }
+}
+
+impl<M, K> Duck<M, K>
+where
+    M: super::Source,
+    K: super::Source,
+{
+    // main and key must have the same samplerate and channels
+    pub fn new(main: M, key: K, settings: Rc<Cell<Ducking>>) -> Self {
+        Self {
+            main,
+            key,
+            settings,
+            level: 0.0,
+            gain: 1.0,
+            buffer: vec![],
+        }
+    }
+}
+
+impl<M, K> super::Source for Duck<M, K>
+where
+    M: super::Source,
+    K: super::Source,
+{
+    fn samplerate(&self) -> f32 {
+        self.main.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.main.channels()
+    }
+
+    fn len(&self) -> Option<u64> {
+        Some(self.main.len()?.max(self.key.len()?))
+    }
+
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let main = self.main.force_fill(buffer)?;
+        buffer[main..].iter_mut().for_each(|s| *s = 0.0);
+        self.buffer.resize(buffer.len(), 0.0);
+        let key = self.key.force_fill(&mut self.buffer)?;
+        self.buffer[key..].iter_mut().for_each(|s| *s = 0.0);
+
+        let settings = self.settings.get();
+        let samplerate = self.samplerate();
+        let threshold = f32::powf(10.0, settings.threshold / 20.0);
+        let ducked = f32::powf(10.0, -settings.depth.abs() / 20.0);
+        let detect = coefficient(DETECT_RELEASE, samplerate);
+        let attack = coefficient(settings.attack, samplerate);
+        let release = coefficient(settings.release, samplerate);
+
+        let channels = self.channels() as usize;
+        let amt = main.max(key);
+        for (out, key) in buffer[..amt]
+            .chunks_mut(channels)
+            .zip(self.buffer[..amt].chunks(channels))
+        {
+            // peaks right away, but hangs on through gaps between words
+            let peak = key.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            self.level = peak.max(self.level * detect);
+
+            let target = if self.level > threshold { ducked } else { 1.0 };
+            let coef = if target < self.gain { attack } else { release };
+            self.gain = target + (self.gain - target) * coef;
+
+            for (o, k) in out.iter_mut().zip(key) {
+                *o = *o * self.gain + k;
+            }
+        }
+        Ok(amt)
+    }
+
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+        self.main.seek(frame)?;
+        self.key.seek(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Duck, Ducking};
+    use crate::source::{Curve, Sine};
+    use crate::Source;
+
+    #[test]
+    fn ducks_only_while_key_plays() {
+        let samplerate = 1000.0;
+        let main = Sine::new(samplerate, 1, 50.0);
+        // key is silent, then talks from 1s to 2s, then is silent again
+        let key = Sine::new(samplerate, 1, 100.0)
+            .envelope()
+            .point(0.0, 0.0, Curve::Linear)
+            .point(1.0, 0.0, Curve::Linear)
+            .point(1.001, 1.0, Curve::Linear)
+            .point(2.0, 1.0, Curve::Linear)
+            .point(2.001, 0.0, Curve::Linear);
+        let settings = Ducking::default();
+        let mut duck = Duck::new(main, key, std::rc::Rc::new(settings.into()));
+
+        let mut buffer = vec![0.0; 1000];
+        duck.fill(&mut buffer).unwrap();
+        assert!((duck.gain - 1.0).abs() < 0.01);
+
+        duck.fill(&mut buffer).unwrap();
+        let ducked = f32::powf(10.0, -settings.depth / 20.0);
+        assert!((duck.gain - ducked).abs() < 0.01);
+
+        // and back up, once it's had time to recover
+        duck.fill(&mut buffer).unwrap();
+        duck.fill(&mut buffer).unwrap();
+        assert!((duck.gain - 1.0).abs() < 0.03);
+    }
+}
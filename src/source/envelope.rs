@@ -0,0 +1,268 @@
+use std::sync::{Arc, Mutex};
+
+// quietest gain an exponential fade passes through, about -60dB
+const EXPONENTIAL_FLOOR: f32 = 0.001;
+
+// how gain moves from one breakpoint to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    Linear,
+    // sine shaped, so a fade in and out together keep the same power
+    EqualPower,
+    // even in decibels, which sounds even to the ear
+    Exponential,
+}
+
+impl Curve {
+    // gain p of the way from a to b
+    fn apply(&self, a: f32, b: f32, p: f32) -> f32 {
+        match self {
+            Curve::Linear => a + (b - a) * p,
+            Curve::EqualPower => {
+                let angle = p * std::f32::consts::FRAC_PI_2;
+                if b >= a {
+                    a + (b - a) * angle.sin()
+                } else {
+                    b + (a - b) * angle.cos()
+                }
+            }
+            Curve::Exponential => {
+                if p >= 1.0 {
+                    return b;
+                }
+                let a = a.max(EXPONENTIAL_FLOOR);
+                let b = b.max(EXPONENTIAL_FLOOR);
+                a * (b / a).powf(p)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    frame: u64,
+    gain: f32,
+    // how to get here from the point before
+    curve: Curve,
+}
+
+#[derive(Debug, Default)]
+struct Points {
+    // sorted by frame
+    points: Vec<Point>,
+    // stop playing here, in frames
+    end: Option<u64>,
+}
+
+impl Points {
+    fn gain(&self, frame: u64) -> f32 {
+        let next = self.points.partition_point(|p| p.frame <= frame);
+        let (from_frame, from_gain) = match next.checked_sub(1) {
+            Some(i) => (self.points[i].frame, self.points[i].gain),
+            None => (0, 1.0),
+        };
+        match self.points.get(next) {
+            Some(to) => {
+                let p = (frame - from_frame) as f32 / (to.frame - from_frame) as f32;
+                to.curve.apply(from_gain, to.gain, p)
+            }
+            None => from_gain,
+        }
+    }
+
+    fn insert(&mut self, point: Point) {
+        let idx = self.points.partition_point(|p| p.frame <= point.frame);
+        self.points.insert(idx, point);
+    }
+}
+
+// a source with its gain changing over time, by breakpoints
+pub struct Envelope<S> {
+    source: S,
+    points: Arc<Mutex<Points>>,
+    pos: u64,
+}
+
+// changes an envelope's gain, even after its source is scheduled.
+// times are in seconds from the start of the source.
+#[derive(Clone)]
+pub struct Fader {
+    points: Arc<Mutex<Points>>,
+    samplerate: f32,
+}
+
+impl<S> Envelope<S>
+where
+    S: super::Source,
+{
+    // starts at full volume, with no breakpoints
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            points: Arc::new(Mutex::new(Points::default())),
+            pos: 0,
+        }
+    }
+
+    // something to change this envelope with later
+    pub fn fader(&self) -> Fader {
+        Fader {
+            points: self.points.clone(),
+            samplerate: self.source.samplerate(),
+        }
+    }
+
+    pub fn point(self, at: f32, gain: f32, curve: Curve) -> Self {
+        self.fader().point(at, gain, curve);
+        self
+    }
+
+    pub fn fade(self, start: f32, gain: f32, duration: f32, curve: Curve) -> Self {
+        self.fader().fade(start, gain, duration, curve);
+        self
+    }
+
+    pub fn fade_in(self, duration: f32, curve: Curve) -> Self {
+        self.fader().fade_in(duration, curve);
+        self
+    }
+
+    pub fn fade_out_at(self, start: f32, duration: f32, curve: Curve) -> Self {
+        self.fader().fade_out_at(start, duration, curve);
+        self
+    }
+}
+
+impl Fader {
+    fn frames(&self, seconds: f32) -> u64 {
+        (seconds.max(0.0) * self.samplerate) as u64
+    }
+
+    // reach this gain at this time, coming from the point before
+    pub fn point(&self, at: f32, gain: f32, curve: Curve) {
+        let frame = self.frames(at);
+        self.points
+            .lock()
+            .unwrap()
+            .insert(Point { frame, gain, curve });
+    }
+
+    // hold the gain at start, then move to gain over duration
+    pub fn fade(&self, start: f32, gain: f32, duration: f32, curve: Curve) {
+        let start = self.frames(start);
+        let end = start + self.frames(duration).max(1);
+        let mut points = self.points.lock().unwrap();
+        let from = points.gain(start);
+        points.insert(Point {
+            frame: start,
+            gain: from,
+            curve: Curve::Linear,
+        });
+        points.insert(Point {
+            frame: end,
+            gain,
+            curve,
+        });
+    }
+
+    // come up from silence at the very start
+    pub fn fade_in(&self, duration: f32, curve: Curve) {
+        self.point(0.0, 0.0, Curve::Linear);
+        self.fade(0.0, 1.0, duration, curve);
+    }
+
+    // go down to silence, then end
+    pub fn fade_out_at(&self, start: f32, duration: f32, curve: Curve) {
+        self.fade(start, 0.0, duration, curve);
+        let end = self.frames(start) + self.frames(duration).max(1);
+        let mut points = self.points.lock().unwrap();
+        points.end = Some(points.end.map_or(end, |e| e.min(end)));
+    }
+}
+
+impl<S> super::Source for Envelope<S>
+where
+    S: super::Source,
+{
+    fn samplerate(&self) -> f32 {
+        self.source.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn len(&self) -> Option<u64> {
+        let end = self.points.lock().unwrap().end;
+        match (self.source.len(), end) {
+            (Some(len), Some(end)) => Some(len.min(end)),
+            (len, None) => len,
+            (None, end) => end,
+        }
+    }
+
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let channels = self.channels() as usize;
+        let points = self.points.lock().unwrap();
+        let size = match points.end {
+            Some(end) => buffer
+                .len()
+                .min(end.saturating_sub(self.pos) as usize * channels),
+            None => buffer.len(),
+        };
+        let amt = self.source.fill(&mut buffer[..size])?;
+        for (i, frame) in buffer[..amt].chunks_mut(channels).enumerate() {
+            let gain = points.gain(self.pos + i as u64);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        self.pos += (amt / channels) as u64;
+        Ok(amt)
+    }
+
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+        self.pos = frame;
+        self.source.seek(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Curve;
+    use crate::source::Sine;
+    use crate::Source;
+
+    #[test]
+    fn curves_meet_their_ends() {
+        for curve in [Curve::Linear, Curve::EqualPower, Curve::Exponential] {
+            for (a, b) in [(0.0, 1.0), (1.0, 0.0), (0.5, 0.25)] {
+                assert!((curve.apply(a, b, 0.0) - a).abs() < 0.002);
+                assert!((curve.apply(a, b, 1.0) - b).abs() < 1e-6);
+            }
+        }
+
+        // opposite equal power fades keep the same power all the way
+        for i in 0..=10 {
+            let p = i as f32 / 10.0;
+            let fade_in = Curve::EqualPower.apply(0.0, 1.0, p);
+            let fade_out = Curve::EqualPower.apply(1.0, 0.0, p);
+            assert!((fade_in * fade_in + fade_out * fade_out - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn fade_out_ends_source() {
+        // a sine at a quarter of the samplerate is 1 every fourth sample from 1
+        let mut envelope = Sine::new(100.0, 1, 25.0)
+            .envelope()
+            .fade_in(0.2, Curve::Linear)
+            .fade_out_at(0.6, 0.2, Curve::Linear);
+        assert_eq!(envelope.len(), Some(80));
+
+        let mut buffer = vec![0.0; 100];
+        assert_eq!(envelope.force_fill(&mut buffer).unwrap(), 80);
+        // halfway up the fade in, full in the middle, halfway down the fade out
+        assert!((buffer[9] - 0.45).abs() < 1e-3);
+        assert!((buffer[41] - 1.0).abs() < 1e-3);
+        assert!((buffer[69] - 0.55).abs() < 1e-3);
+    }
+}
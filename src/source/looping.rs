@@ -0,0 +1,165 @@
+// a source that plays another over and over, forever
+pub struct Loop<S> {
+    source: S,
+    // frames in one pass of source
+    len: u64,
+    // frames of overlap between the end of one pass and the next
+    fade: u64,
+    // the start of source, to fade into while the end plays
+    head: Vec<f32>,
+    // where we are in source, in frames
+    pos: u64,
+}
+
+impl<S> Loop<S>
+where
+    S: super::Source,
+{
+    // crossfade from the end back into the start over this many seconds
+    pub fn new(mut source: S, crossfade: f32) -> anyhow::Result<Self> {
+        let len = source
+            .len()
+            .ok_or_else(|| anyhow::anyhow!("cannot loop a source of unknown length"))?;
+        if len == 0 {
+            anyhow::bail!("cannot loop an empty source");
+        }
+
+        // at most half, so fades don't overlap each other
+        let fade = ((crossfade.max(0.0) * source.samplerate()) as u64).min(len / 2);
+        let mut head = vec![0.0; fade as usize * source.channels() as usize];
+        let amt = source.force_fill(&mut head)?;
+        head.truncate(amt);
+        source.seek(0)?;
+
+        Ok(Self {
+            len,
+            fade: amt as u64 / source.channels() as u64,
+            source,
+            head,
+            pos: 0,
+        })
+    }
+}
+
+impl<S> super::Source for Loop<S>
+where
+    S: super::Source,
+{
+    fn samplerate(&self) -> f32 {
+        self.source.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn len(&self) -> Option<u64> {
+        None
+    }
+
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let channels = self.channels() as usize;
+        let mut filled = 0;
+        while filled < buffer.len() {
+            // passes after the first start late, having faded in already
+            if self.pos >= self.len {
+                self.source.seek(self.fade)?;
+                self.pos = self.fade;
+            }
+
+            let want = (buffer.len() - filled).min((self.len - self.pos) as usize * channels);
+            let chunk = &mut buffer[filled..filled + want];
+            let amt = self.source.fill(chunk)?;
+            if amt == 0 {
+                // shorter than it said, so loop from where it really ends
+                if self.pos <= self.fade {
+                    break;
+                }
+                self.len = self.pos;
+                self.fade = self.fade.min(self.len / 2);
+                continue;
+            }
+
+            // equal power, since the end and start aren't alike
+            let fade_start = self.len - self.fade;
+            for (i, frame) in chunk[..amt].chunks_mut(channels).enumerate() {
+                let pos = self.pos + i as u64;
+                if pos < fade_start {
+                    continue;
+                }
+                let k = (pos - fade_start) as usize;
+                let t = (k as f32 + 0.5) / self.fade as f32 * std::f32::consts::FRAC_PI_2;
+                for (c, sample) in frame.iter_mut().enumerate() {
+                    *sample = *sample * t.cos() + self.head[k * channels + c] * t.sin();
+                }
+            }
+
+            self.pos += (amt / channels) as u64;
+            filled += amt;
+        }
+        Ok(filled)
+    }
+
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+        self.pos = if frame < self.len {
+            frame
+        } else {
+            let pass = self.len - self.fade;
+            self.fade + (frame - self.len) % pass
+        };
+        self.source.seek(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::Media;
+    use crate::Source;
+
+    const SAMPLERATE: u32 = 8000;
+    const FRAMES: u32 = 800;
+
+    // a 16-bit mono wav of a rising ramp
+    fn generate_wav() -> Vec<u8> {
+        crate::source::test_wav(SAMPLERATE, 1, (0..FRAMES).map(|i| (i * 40) as i16))
+    }
+
+    fn media() -> Media {
+        Media::new(std::io::Cursor::new(generate_wav())).unwrap()
+    }
+
+    #[test]
+    fn loops_without_crossfade() {
+        let mut once = vec![0.0; FRAMES as usize];
+        assert_eq!(media().force_fill(&mut once).unwrap(), once.len());
+
+        let mut looped = media().looping(0.0).unwrap();
+        assert_eq!(looped.len(), None);
+        let mut buffer = vec![0.0; 3 * FRAMES as usize + 100];
+        assert_eq!(looped.force_fill(&mut buffer).unwrap(), buffer.len());
+        for (i, sample) in buffer.iter().enumerate() {
+            assert_eq!(*sample, once[i % once.len()]);
+        }
+    }
+
+    #[test]
+    fn crossfades_into_start() {
+        let mut once = vec![0.0; FRAMES as usize];
+        assert_eq!(media().force_fill(&mut once).unwrap(), once.len());
+
+        // 10ms is 80 frames
+        let fade = 80;
+        let mut looped = media().looping(0.01).unwrap();
+        let mut buffer = vec![0.0; 2 * FRAMES as usize];
+        assert_eq!(looped.force_fill(&mut buffer).unwrap(), buffer.len());
+
+        let len = FRAMES as usize;
+        // untouched before the fade
+        assert_eq!(&buffer[..len - fade], &once[..len - fade]);
+        // mostly the end at the start of the fade, mostly the start after
+        assert!((buffer[len - fade] - once[len - fade]).abs() < 0.01);
+        assert!((buffer[len - 1] - once[fade - 1]).abs() < 0.01);
+        // then carry on after the part that faded in
+        assert_eq!(&buffer[len..2 * len - 2 * fade], &once[fade..len - fade]);
+    }
+}
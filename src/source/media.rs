@@ -14,6 +14,8 @@ pub struct Media {
     used: usize,
     reset_next: bool,
     end_of_stream: bool,
+    // after a seek, discard decoded frames before this timestamp
+    seek_target: Option<u64>,
 }
 
 struct MediaReader<R> {
@@ -107,6 +109,7 @@ impl Media {
             used: 0,
             reset_next: true,
             end_of_stream: false,
+            seek_target: None,
         })
     }
 
@@ -144,6 +147,18 @@ impl Media {
 
             match self.decoder.decode(&packet) {
                 Ok(decoded) => {
+                    // skip frames we seeked past, if any
+                    let mut skip = 0;
+                    if let Some(target) = self.seek_target {
+                        let frames = decoded.frames() as u64;
+                        if packet.ts() + frames <= target {
+                            continue;
+                        }
+                        skip = target.saturating_sub(packet.ts()) as usize
+                            * decoded.spec().channels.count();
+                        self.seek_target = None;
+                    }
+
                     let current = self.buffer.as_ref().map(|b| {
                         b.capacity() >= decoded.capacity() * decoded.spec().channels.count()
                     });
@@ -158,10 +173,11 @@ impl Media {
                     if let Some(buffer) = self.buffer.as_mut() {
                         buffer.copy_interleaved_ref(decoded);
 
-                        if buffer.samples().is_empty() {
+                        if buffer.samples().len() <= skip {
                             continue;
                         }
 
+                        self.used = skip;
                         return Ok(true);
                     } else {
                         anyhow::bail!("no buffer to write to");
@@ -224,7 +240,7 @@ impl super::Source for Media {
         self.decoder.codec_params().n_frames
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
         let channels = self.channels();
         let ourlen = (buffer.len() / channels as usize) * channels as usize;
 
@@ -238,24 +254,19 @@ impl super::Source for Media {
                 amt
             });
 
-            match more {
-                Ok(true) => continue,
-                Ok(false) => break,
-                Err(_) => {
-                    // this is a legitimate error, but we have no way to
-                    // propogate it
-                    return 0;
-                }
+            if !more? {
+                break;
             }
         }
 
-        written
+        Ok(written)
     }
 
     fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
-        // FIXME precision seeking, also, is TimeStamp correct?
-        self.format.seek(
-            SeekMode::Coarse,
+        // audio tracks use a time base of 1 / samplerate, so
+        // timestamps are frames
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
             SeekTo::TimeStamp {
                 ts: frame,
                 track_id: self.track_id,
@@ -264,6 +275,56 @@ impl super::Source for Media {
         self.decoder.reset();
         self.reset_next = true;
         self.end_of_stream = false;
+        // the reader lands on a packet at or before what we asked for,
+        // so decode forward and throw away the difference
+        self.seek_target = Some(seeked.required_ts);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Media;
+    use crate::Source;
+
+    const SAMPLERATE: u32 = 44100;
+    const CHANNELS: u16 = 2;
+    const FRAMES: u32 = SAMPLERATE * 3;
+
+    // a 16-bit stereo wav full of a deterministic, non-repeating signal
+    fn generate_wav() -> Vec<u8> {
+        let samples =
+            (0..FRAMES * CHANNELS as u32).map(|i| (i.wrapping_mul(2654435761) >> 16) as i16);
+        crate::source::test_wav(SAMPLERATE, CHANNELS, samples)
+    }
+
+    fn decode_all(media: &mut Media) -> Vec<f32> {
+        let mut out = vec![];
+        let mut buffer = vec![0.0; 4096];
+        loop {
+            let amt = media.fill(&mut buffer).unwrap();
+            if amt == 0 {
+                break;
+            }
+            out.extend_from_slice(&buffer[..amt]);
+        }
+        out
+    }
+
+    #[test]
+    fn accurate_seek() {
+        let wav = generate_wav();
+        let mut media = Media::new(std::io::Cursor::new(wav)).unwrap();
+        assert_eq!(media.len(), Some(FRAMES as u64));
+        let straight = decode_all(&mut media);
+        assert_eq!(straight.len(), (FRAMES * CHANNELS as u32) as usize);
+
+        for frame in [0, 1, 1000, 4097, 12345, 100000, FRAMES as u64 - 1] {
+            media.seek(frame).unwrap();
+            let seeked = decode_all(&mut media);
+            let offset = frame as usize * CHANNELS as usize;
+            assert_eq!(seeked.len(), straight.len() - offset, "seek to {}", frame);
+            assert!(seeked == straight[offset..], "seek to {}", frame);
+        }
+    }
+}
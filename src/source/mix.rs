@@ -50,12 +50,12 @@ where
         self.source.len()
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
         let outchannels = self.mix.len();
         let inchannels = self.inchannels as usize;
         self.buffer
             .resize(buffer.len() * inchannels / outchannels, 0.0);
-        let samples = self.source.fill(&mut self.buffer);
+        let samples = self.source.fill(&mut self.buffer)?;
         let frames = samples / inchannels;
         for f in 0..frames {
             let base_in = f * inchannels;
@@ -67,7 +67,7 @@ where
                 }
             }
         }
-        frames * outchannels
+        Ok(frames * outchannels)
     }
 
     fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
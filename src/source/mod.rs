@@ -1,34 +1,42 @@
+mod duck;
+mod envelope;
+mod looping;
 mod media;
 mod mix;
 mod resample;
 mod sine;
+mod trim;
 mod volume;
 
+pub use duck::{Duck, Ducking};
+pub use envelope::{Curve, Envelope, Fader};
+pub use looping::Loop;
 pub use media::Media;
 pub use mix::Mix;
 pub use resample::Resample;
 pub use sine::Sine;
-pub use volume::Volume;
+pub use trim::Trim;
+pub use volume::{measure_lufs, Volume};
 
 pub trait Source {
     fn samplerate(&self) -> f32;
     fn channels(&self) -> u16;
     fn len(&self) -> Option<u64>;
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize;
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize>;
     fn seek(&mut self, frame: u64) -> anyhow::Result<()>;
 
-    fn force_fill(&mut self, mut buffer: &mut [f32]) -> usize {
+    fn force_fill(&mut self, mut buffer: &mut [f32]) -> anyhow::Result<usize> {
         let mut filled = 0;
         while buffer.len() > 0 {
-            let f = self.fill(buffer);
+            let f = self.fill(buffer)?;
             if f == 0 {
                 break;
             }
             buffer = &mut buffer[f..];
             filled += f;
         }
-        filled
+        Ok(filled)
     }
 
     fn resample(self, samplerate: f32) -> Resample<Self>
@@ -75,6 +83,54 @@ pub trait Source {
         self.reformat(other.samplerate(), other.channels())
     }
 
+    fn trim(self, threshold: f32) -> anyhow::Result<Trim<Self>>
+    where
+        Self: Sized,
+    {
+        Trim::new(self, threshold)
+    }
+
+    fn trim_cached<P>(
+        self,
+        threshold: f32,
+        cache: &crate::AnalysisCache,
+        path: P,
+    ) -> anyhow::Result<Trim<Self>>
+    where
+        Self: Sized,
+        P: AsRef<std::path::Path>,
+    {
+        Trim::new_cached(self, threshold, cache, path)
+    }
+
+    fn envelope(self) -> Envelope<Self>
+    where
+        Self: Sized,
+    {
+        Envelope::new(self)
+    }
+
+    fn fade_in(self, duration: f32, curve: Curve) -> Envelope<Self>
+    where
+        Self: Sized,
+    {
+        Envelope::new(self).fade_in(duration, curve)
+    }
+
+    fn fade_out_at(self, start: f32, duration: f32, curve: Curve) -> Envelope<Self>
+    where
+        Self: Sized,
+    {
+        Envelope::new(self).fade_out_at(start, duration, curve)
+    }
+
+    fn looping(self, crossfade: f32) -> anyhow::Result<Loop<Self>>
+    where
+        Self: Sized,
+    {
+        Loop::new(self, crossfade)
+    }
+
     fn volume(self, volume: f32) -> Volume<Self>
     where
         Self: Sized,
@@ -88,4 +144,37 @@ pub trait Source {
     {
         Volume::new_lufs(self, lufs)
     }
+
+    fn normalize_cached<P>(self, lufs: f32, cache: &crate::AnalysisCache, path: P) -> Volume<Self>
+    where
+        Self: Sized + Send + 'static,
+        P: AsRef<std::path::Path>,
+    {
+        Volume::new_lufs_cached(self, lufs, cache, path)
+    }
+}
+
+// a 16-bit wav file in memory, from interleaved samples, for tests
+#[cfg(test)]
+pub(crate) fn test_wav<I>(samplerate: u32, channels: u16, samples: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = i16>,
+{
+    let data: Vec<u8> = samples.into_iter().flat_map(i16::to_le_bytes).collect();
+    let datalen = data.len() as u32;
+    let mut wav = Vec::with_capacity(44 + data.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + datalen).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&samplerate.to_le_bytes());
+    wav.extend_from_slice(&(samplerate * channels as u32 * 2).to_le_bytes());
+    wav.extend_from_slice(&(channels * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&datalen.to_le_bytes());
+    wav.extend_from_slice(&data);
+    wav
 }
@@ -1,10 +1,24 @@
-use crate::samplerate::{ConverterType, SampleRate};
+#[cfg(not(feature = "libsamplerate"))]
+use crate::polyphase::Polyphase as Converter;
+#[cfg(feature = "libsamplerate")]
+use crate::samplerate::{ConverterType, SampleRate as Converter};
+
+// libsamplerate when built with it, otherwise our own
+#[cfg(feature = "libsamplerate")]
+fn converter(channels: u16) -> Converter {
+    Converter::new(ConverterType::SincFastest, channels).unwrap()
+}
+
+#[cfg(not(feature = "libsamplerate"))]
+fn converter(channels: u16) -> Converter {
+    Converter::new(channels)
+}
 
 pub struct Resample<S> {
     source: S,
     samplerate: f32,
     inrate: f32,
-    converter: SampleRate,
+    converter: Converter,
     buffer: Vec<f32>,
     bufferstart: usize,
 }
@@ -15,8 +29,7 @@ where
 {
     pub fn new(source: S, samplerate: f32) -> Self {
         Self {
-            converter: SampleRate::new(ConverterType::SincFastest, source.channels())
-                .unwrap(),
+            converter: converter(source.channels()),
             inrate: source.samplerate(),
             buffer: vec![],
             bufferstart: 0,
@@ -44,7 +57,7 @@ where
             .map(|s| (s as f32 * self.samplerate / self.source.samplerate()).round() as u64)
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
         let ratio = self.samplerate / self.inrate;
         let mut innerlen = buffer.len() as f32 / ratio;
         innerlen += self.source.channels() as f32 * 2.0;
@@ -52,22 +65,19 @@ where
             self.buffer.resize(innerlen.round() as usize, 0.0);
         }
 
-        let avail = self.source.fill(&mut self.buffer[self.bufferstart..]);
-        let (input_used, output_gen) = self
-            .converter
-            .process(
-                ratio as f64,
-                &self.buffer[..avail + self.bufferstart],
-                buffer,
-            )
-            .unwrap();
+        let avail = self.source.fill(&mut self.buffer[self.bufferstart..])?;
+        let (input_used, output_gen) = self.converter.process(
+            ratio as f64,
+            &self.buffer[..avail + self.bufferstart],
+            buffer,
+        )?;
         self.buffer.copy_within(input_used.., 0);
         self.bufferstart = avail + self.bufferstart - input_used;
-        output_gen
+        Ok(output_gen)
     }
 
     fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
-        self.converter.reset().unwrap();
+        self.converter = converter(self.source.channels());
         self.bufferstart = 0;
         self.source
             .seek((frame as f32 * self.source.samplerate() / self.samplerate).round() as u64)
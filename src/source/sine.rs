@@ -31,7 +31,7 @@ impl super::Source for Sine {
         None
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
         for i in 0..buffer.len() {
             let v = (self.omega * self.sample as f32 / self.samplerate).sin();
 
@@ -43,7 +43,7 @@ impl super::Source for Sine {
                 self.channel = 0;
             }
         }
-        buffer.len()
+        Ok(buffer.len())
     }
 
     fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
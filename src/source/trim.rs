@@ -0,0 +1,198 @@
+// sound this close to the loud part is kept, in seconds
+const MARGIN: f32 = 0.01;
+
+// a source with its leading and trailing silence cut off
+pub struct Trim<S> {
+    source: S,
+    // the kept frames of source, as [start, end)
+    start: u64,
+    end: u64,
+    pos: u64,
+}
+
+impl<S> Trim<S>
+where
+    S: super::Source,
+{
+    // listen to the whole source for anything louder than threshold, in
+    // dBFS. the source must end.
+    pub fn new(mut source: S, threshold: f32) -> anyhow::Result<Self> {
+        let range = Self::measure(&mut source, threshold)?;
+        Self::with_range(source, range)
+    }
+
+    // like new, but look up and store where to cut in a cache
+    pub fn new_cached<P>(
+        mut source: S,
+        threshold: f32,
+        cache: &crate::AnalysisCache,
+        path: P,
+    ) -> anyhow::Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        if let Some(range) = cache.get_trim(&path, threshold) {
+            return Self::with_range(source, range);
+        }
+        let range = Self::measure(&mut source, threshold)?;
+        // failing to cache isn't worth failing playback over
+        let _ = cache.insert_trim(&path, threshold, range);
+        Self::with_range(source, range)
+    }
+
+    // keep only frames [start, end) of the source
+    pub fn with_range(mut source: S, (start, end): (u64, u64)) -> anyhow::Result<Self> {
+        source.seek(start)?;
+        Ok(Self {
+            source,
+            start,
+            end,
+            pos: 0,
+        })
+    }
+
+    // the frames worth keeping, as [start, end)
+    fn measure(source: &mut S, threshold: f32) -> anyhow::Result<(u64, u64)> {
+        let channels = source.channels() as usize;
+        let level = f32::powf(10.0, threshold / 20.0);
+
+        let mut buffer = vec![0.0; 4096 * channels];
+        let mut frame = 0;
+        let mut first = None;
+        let mut last = None;
+        loop {
+            let amt = source.fill(&mut buffer)?;
+            if amt == 0 {
+                break;
+            }
+            for (i, samples) in buffer[..amt].chunks(channels).enumerate() {
+                if samples.iter().any(|s| s.abs() > level) {
+                    first.get_or_insert(frame + i as u64);
+                    last = Some(frame + i as u64);
+                }
+            }
+            frame += (amt / channels) as u64;
+        }
+
+        // all silence trims down to nothing
+        let margin = (MARGIN * source.samplerate()) as u64;
+        Ok(match (first, last) {
+            (Some(first), Some(last)) => {
+                (first.saturating_sub(margin), (last + 1 + margin).min(frame))
+            }
+            _ => (0, 0),
+        })
+    }
+
+    // how many frames were cut off the start
+    pub fn offset(&self) -> u64 {
+        self.start
+    }
+
+    // keep all of the source
+    pub fn whole(source: S) -> Self {
+        let end = source.len().unwrap_or(u64::MAX);
+        Self {
+            source,
+            start: 0,
+            end,
+            pos: 0,
+        }
+    }
+}
+
+impl<S> super::Source for Trim<S>
+where
+    S: super::Source,
+{
+    fn samplerate(&self) -> f32 {
+        self.source.samplerate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn len(&self) -> Option<u64> {
+        if self.end == u64::MAX {
+            self.source.len()
+        } else {
+            Some(self.end - self.start)
+        }
+    }
+
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let channels = self.channels() as u64;
+        let remaining = (self.end - self.start).saturating_sub(self.pos);
+        let size = buffer
+            .len()
+            .min(remaining.saturating_mul(channels) as usize);
+        let amt = self.source.fill(&mut buffer[..size])?;
+        self.pos += amt as u64 / channels;
+        Ok(amt)
+    }
+
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+        self.pos = frame;
+        self.source.seek(self.start + frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::Media;
+    use crate::Source;
+
+    const SAMPLERATE: u32 = 8000;
+
+    // a 16-bit mono wav of 1s silence, 2s noise, 1s silence
+    fn generate_wav() -> Vec<u8> {
+        let samples = (0..SAMPLERATE * 4).map(|i| {
+            let loud = (SAMPLERATE..SAMPLERATE * 3).contains(&i);
+            if loud {
+                ((i.wrapping_mul(2654435761) >> 16) as i16) / 2
+            } else {
+                0
+            }
+        });
+        crate::source::test_wav(SAMPLERATE, 1, samples)
+    }
+
+    #[test]
+    fn trims_silence() {
+        let media = Media::new(std::io::Cursor::new(generate_wav())).unwrap();
+        let mut trimmed = media.trim(-60.0).unwrap();
+
+        let margin = SAMPLERATE as u64 / 100;
+        let expected = 2 * SAMPLERATE as u64 + 2 * margin;
+        assert_eq!(trimmed.len(), Some(expected));
+
+        let mut buffer = vec![0.0; 3 * SAMPLERATE as usize];
+        let amt = trimmed.force_fill(&mut buffer).unwrap();
+        assert_eq!(amt as u64, expected);
+        assert!(buffer[..margin as usize].iter().all(|s| *s == 0.0));
+        assert!(buffer[margin as usize..amt].iter().any(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn remembers_trim() {
+        let path = std::env::temp_dir().join(format!("sprunk-trim-{}.wav", std::process::id()));
+        std::fs::write(&path, generate_wav()).unwrap();
+        let cache = crate::AnalysisCache::new();
+        let open = || Media::new(std::fs::File::open(&path).unwrap()).unwrap();
+
+        let first = open().trim_cached(-60.0, &cache, &path).unwrap();
+        let range = cache.get_trim(&path, -60.0);
+        assert_eq!(
+            range,
+            Some((first.offset(), first.offset() + first.len().unwrap()))
+        );
+        assert_eq!(cache.get_trim(&path, -50.0), None);
+
+        // a different range in the cache is believed without listening
+        cache.insert_trim(&path, -60.0, (10, 20)).unwrap();
+        let second = open().trim_cached(-60.0, &cache, &path).unwrap();
+        assert_eq!((second.offset(), second.len()), (10, Some(10)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -20,9 +20,13 @@ impl<S> VolumeState<S> {
         let m = std::mem::replace(self, VolumeState::Failed);
         match m {
             VolumeState::Calculating(handle, target) => {
-                if let Ok(Ok((src, measured))) = handle.join() {
-                    let volume = f32::powf(10.0, (target - measured) / 20.0);
-                    *self = VolumeState::Ready(src, volume);
+                match handle.join() {
+                    Ok(Ok((src, measured))) => {
+                        let volume = f32::powf(10.0, (target - measured) / 20.0);
+                        *self = VolumeState::Ready(src, volume);
+                    }
+                    Ok(Err(e)) => return Err(e.context("volume normalization failed")),
+                    Err(_) => anyhow::bail!("volume normalization panicked"),
                 }
                 return self.get();
             }
@@ -44,29 +48,43 @@ where
         }
     }
 
-    pub fn new_lufs(mut source: S, lufs: f32) -> Self
+    pub fn new_lufs(source: S, lufs: f32) -> Self
     where
         S: Send + 'static,
+    {
+        Self::new_lufs_with(source, lufs, |_| ())
+    }
+
+    // like new_lufs, but look up and store the measurement in a cache
+    pub fn new_lufs_cached<P>(source: S, lufs: f32, cache: &crate::AnalysisCache, path: P) -> Self
+    where
+        S: Send + 'static,
+        P: AsRef<std::path::Path>,
+    {
+        if let Some(measured) = cache.get(&path) {
+            return Self::new(source, f32::powf(10.0, (lufs - measured) / 20.0));
+        }
+
+        let cache = cache.clone();
+        let path = path.as_ref().to_owned();
+        Self::new_lufs_with(source, lufs, move |measured| {
+            // failing to cache isn't worth failing playback over
+            let _ = cache.insert(&path, measured);
+        })
+    }
+
+    fn new_lufs_with<F>(mut source: S, lufs: f32, measured: F) -> Self
+    where
+        S: Send + 'static,
+        F: FnOnce(f32) + Send + 'static,
     {
         let samplerate = source.samplerate();
         let channels = source.channels();
         let len = source.len();
 
         let handle = std::thread::spawn(move || {
-            let mut ebu = ebur128::EbuR128::new(
-                source.channels() as u32,
-                source.samplerate() as u32,
-                ebur128::Mode::I,
-            )?;
-            let mut buffer = vec![0.0; (ebu.rate() * ebu.channels()) as usize];
-            loop {
-                let amt = source.fill(&mut buffer);
-                if amt == 0 {
-                    break;
-                }
-                ebu.add_frames_f32(&mut buffer[..amt])?;
-            }
-            let loudness = ebu.loudness_global()? as f32;
+            let loudness = measure_lufs(&mut source)?;
+            measured(loudness);
             source.seek(0)?;
             Ok((source, loudness))
         });
@@ -79,6 +97,27 @@ where
     }
 }
 
+// integrated loudness of everything left in a source
+pub fn measure_lufs<S>(source: &mut S) -> anyhow::Result<f32>
+where
+    S: super::Source,
+{
+    let mut ebu = ebur128::EbuR128::new(
+        source.channels() as u32,
+        source.samplerate() as u32,
+        ebur128::Mode::I,
+    )?;
+    let mut buffer = vec![0.0; (ebu.rate() * ebu.channels()) as usize];
+    loop {
+        let amt = source.fill(&mut buffer)?;
+        if amt == 0 {
+            break;
+        }
+        ebu.add_frames_f32(&mut buffer[..amt])?;
+    }
+    Ok(ebu.loudness_global()? as f32)
+}
+
 impl<S> super::Source for Volume<S>
 where
     S: super::Source,
@@ -100,15 +139,12 @@ where
         source.seek(frame)
     }
 
-    fn fill(&mut self, buffer: &mut [f32]) -> usize {
-        if let Ok((source, volume)) = self.state.get() {
-            let size = source.fill(buffer);
-            for i in 0..size {
-                buffer[i] *= volume;
-            }
-            size
-        } else {
-            0
+    fn fill(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let (source, volume) = self.state.get()?;
+        let size = source.fill(buffer)?;
+        for i in 0..size {
+            buffer[i] *= volume;
         }
+        Ok(size)
     }
 }
@@ -2,17 +2,27 @@ use crate::{source, Scheduler, Source, Time};
 
 use std::path::PathBuf;
 
+// how long before music is needed to schedule it, in seconds
+const LOOKAHEAD: f32 = 5.0;
+
+// how quickly to fade out skipped music, in seconds
+const SKIP_FADE: f32 = 0.3;
+
+// how long ambience crossfades into itself when it loops, in seconds
+const LOOP_FADE: f32 = 2.0;
+
 pub struct AmbientScheduler {
     crossfade: Time,
 
     root: Scheduler,
     music: Scheduler,
-    ambience: [Scheduler; 2],
-    active_ambience: usize,
-    ambience_source: Option<(f32, Vec<u8>)>,
+    ambience: Scheduler,
+    // when the playing ambience started, and how to fade it out
+    ambience_fader: Option<(Time, source::Fader)>,
+    // silence threshold in dBFS, for trimming music
+    trim: Option<f32>,
 
     music_end: Time,
-    ambience_end: Time,
 }
 
 impl AmbientScheduler {
@@ -21,70 +31,114 @@ impl AmbientScheduler {
             crossfade,
 
             music: root.subscheduler(),
-            ambience: array_init::array_init(|_| root.subscheduler_with_volume(0.0)),
+            ambience: root.subscheduler(),
             root: root,
-            active_ambience: 0,
-            ambience_source: None,
+            ambience_fader: None,
+            trim: None,
 
             music_end: Time::seconds(0.0),
-            ambience_end: Time::seconds(0.0),
         }
     }
 
-    fn load_media(&self, volume: f32, data: Vec<u8>) -> anyhow::Result<source::Volume<source::Media>> {
-        Ok(source::Media::new(std::io::Cursor::new(data))?.volume(volume))
+    // cut silence quieter than threshold dBFS off music, or None to disable
+    pub fn set_trim(&mut self, threshold: Option<f32>) {
+        self.trim = threshold;
     }
 
-    fn load_ambience(&self) -> anyhow::Result<Option<source::Volume<source::Media>>> {
-        if let Some((volume, data)) = &self.ambience_source {
-            Ok(Some(self.load_media(*volume, data.clone())?))
-        } else {
-            Ok(None)
-        }
+    fn load_music(
+        &self,
+        volume: f32,
+        data: Vec<u8>,
+    ) -> anyhow::Result<source::Volume<source::Trim<source::Media>>> {
+        let media = source::Media::new(std::io::Cursor::new(data))?;
+        let media = match self.trim {
+            Some(threshold) => media.trim(threshold)?,
+            None => source::Trim::whole(media),
+        };
+        Ok(media.volume(volume))
     }
 
-    pub async fn add_music(&mut self, volume: f32, data: Vec<u8>) -> anyhow::Result<()> {
-        let source = self.load_media(volume, data)?;
-        let start = self.music_end;
-        let end = self.music.add(start, source).ok_or_else(|| anyhow::anyhow!("unknown sound file length"))?;
+    // ambience plays until it's faded out
+    fn load_ambience(
+        &self,
+        volume: f32,
+        data: Vec<u8>,
+    ) -> anyhow::Result<source::Envelope<source::Volume<source::Loop<source::Media>>>> {
+        let media = source::Media::new(std::io::Cursor::new(data))?;
+        Ok(media.looping(LOOP_FADE)?.volume(volume).envelope())
+    }
 
-        self.music.wait(start).await?;
-        self.music_end = end;
+    // stream time where the next music will start, in seconds
+    pub fn elapsed(&self) -> f32 {
+        self.music_end.to_seconds(self.root.samplerate())
+    }
 
-        if self.ambience_source.is_some() {
-            while self.ambience_end.to_frames(self.root.samplerate()) < self.music_end.to_frames(self.root.samplerate()) {
-                let ambience_source = self.load_ambience()?.unwrap();
-                self.ambience_end = self.ambience[self.active_ambience].add(self.ambience_end, ambience_source).ok_or_else(|| anyhow::anyhow!("unknown sound file length"))?;
-            }
-        }
-        
+    // when it's nearly time for the next music to be scheduled
+    pub fn ready_time(&self) -> Time {
+        Time::seconds((self.elapsed() - LOOKAHEAD).max(0.0))
+    }
+
+    pub fn samplerate(&self) -> f32 {
+        self.root.samplerate()
+    }
+
+    pub fn system_time(&self, time: Time) -> std::time::SystemTime {
+        self.music.system_time(time)
+    }
+
+    pub async fn wait(&mut self, time: Time) -> anyhow::Result<()> {
+        self.music.wait(time).await?;
         Ok(())
     }
 
-    pub async fn add_ambience(&mut self, volume: f32, data: Option<Vec<u8>>) -> anyhow::Result<()> {
-        let source = if let Some(data) = &data {
-            Some(self.load_media(volume, data.clone())?)
-        } else {
-            None
-        };
+    // call f once this time is heard, unless skipped first
+    pub fn mark<F>(&mut self, time: Time, f: F) where F: FnOnce() + 'static {
+        self.music.mark(time, f);
+    }
 
-        let new_active_ambience = (self.active_ambience + 1) % self.ambience.len();
-        let mut old_ambience = &mut self.ambience[self.active_ambience];
-        let mut new_ambience = self.root.subscheduler_with_volume(0.0);
+    pub fn set_paused(&mut self, paused: bool) {
+        self.root.set_paused(paused);
+    }
 
-        old_ambience.set_volume(self.music_end, 0.0, self.crossfade);
-        new_ambience.set_volume(self.music_end, 1.0, self.crossfade);
+    // cut off the music playing now, and start the next from here
+    pub fn skip(&mut self) {
+        let now = self.music.now();
+        let end = now + SKIP_FADE;
+        self.music.cancel_volume(now);
+        self.music.set_volume(now, 0.0, SKIP_FADE);
+        self.music.stop(end);
+        self.music.set_volume(end, 1.0, 0.0);
+        self.music_end = end;
+    }
 
-        let end = if let Some(source) = source {
-            new_ambience.add(self.music_end, source).ok_or_else(|| anyhow::anyhow!("unknown sound file length"))?
-        } else {
-            self.ambience_end
-        };
+    // schedule music, and return when it starts and ends
+    pub fn add_music(&mut self, volume: f32, data: Vec<u8>) -> anyhow::Result<(Time, Time)> {
+        let source = self.load_music(volume, data)?;
+        let start = self.music_end;
+        let end = self.music.add(start, source).ok_or_else(|| anyhow::anyhow!("unknown sound file length"))?;
+        self.music_end = end;
+        Ok((start, end))
+    }
+
+    pub async fn add_ambience(&mut self, volume: f32, data: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let source = data
+            .map(|data| self.load_ambience(volume, data))
+            .transpose()?;
 
-        self.ambience[new_active_ambience] = new_ambience;
-        self.active_ambience = new_active_ambience;
-        self.ambience_end = end;
-        self.ambience_source = data.map(|d| (volume, d));
+        let samplerate = self.root.samplerate();
+        let crossfade = self.crossfade.to_seconds(samplerate);
+
+        // the old ambience loops forever, so this fade also ends it
+        if let Some((start, fader)) = self.ambience_fader.take() {
+            let fade_start = self.music_end.to_seconds(samplerate) - start.to_seconds(samplerate);
+            fader.fade_out_at(fade_start, crossfade, source::Curve::EqualPower);
+        }
+
+        if let Some(source) = source {
+            let source = source.fade_in(crossfade, source::Curve::EqualPower);
+            self.ambience_fader = Some((self.music_end, source.fader()));
+            self.ambience.add(self.music_end, source);
+        }
 
         Ok(())
     }
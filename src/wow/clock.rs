@@ -0,0 +1,93 @@
+use chrono::Timelike;
+
+// in seconds
+const DAY: f32 = 60.0 * 60.0 * 24.0;
+
+// the in-game time of day, which follows stream time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clock {
+    // in-game seconds per stream second
+    pub speed: f32,
+    // seconds since midnight when the stream starts, or None for local time
+    pub start: Option<f32>,
+    // seconds since midnight
+    pub dawn: f32,
+    pub dusk: f32,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            start: None,
+            dawn: 6.0 * 60.0 * 60.0,
+            dusk: 18.0 * 60.0 * 60.0,
+        }
+    }
+}
+
+impl Clock {
+    // parse HH:MM or HH:MM:SS into seconds since midnight
+    pub fn parse_time_of_day(time: &str) -> anyhow::Result<f32> {
+        let mut r = 0.0;
+        let mut scale = 60.0 * 60.0;
+        for part in time.split(":") {
+            if scale < 1.0 {
+                anyhow::bail!("bad time of day: {:?}", time);
+            }
+            r += scale
+                * part
+                    .parse::<f32>()
+                    .map_err(|_| anyhow::anyhow!("bad time of day: {:?}", time))?;
+            scale /= 60.0;
+        }
+        if !(0.0..DAY).contains(&r) {
+            anyhow::bail!("bad time of day: {:?}", time);
+        }
+        Ok(r)
+    }
+
+    // the time of day to start at, in seconds since midnight
+    pub fn start(&self) -> f32 {
+        self.start.unwrap_or_else(|| {
+            let now = chrono::Local::now();
+            now.num_seconds_from_midnight() as f32 + now.nanosecond() as f32 / 1e9
+        })
+    }
+
+    pub fn time_of_day(&self, start: f32, elapsed: f32) -> f32 {
+        (start + elapsed * self.speed).rem_euclid(DAY)
+    }
+
+    pub fn is_night(&self, start: f32, elapsed: f32) -> bool {
+        let t = self.time_of_day(start, elapsed);
+        if self.dawn <= self.dusk {
+            t < self.dawn || t >= self.dusk
+        } else {
+            // night doesn't wrap past midnight
+            t >= self.dusk && t < self.dawn
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Clock;
+
+    #[test]
+    fn day_and_night() {
+        let clock = Clock {
+            speed: 60.0,
+            ..Default::default()
+        };
+        let noon = Clock::parse_time_of_day("12:00").unwrap();
+        assert!(!clock.is_night(noon, 0.0));
+        // 5h59m of game time later, still day
+        assert!(!clock.is_night(noon, 359.0));
+        // 6h later, dusk
+        assert!(clock.is_night(noon, 360.0));
+        // and wrap around midnight until dawn
+        assert!(clock.is_night(noon, 1079.0));
+        assert!(!clock.is_night(noon, 1080.0));
+    }
+}
@@ -5,11 +5,16 @@ use strict_yaml_rust::{StrictYaml, StrictYamlLoader};
 
 use crate::normalize::normalize;
 
+use super::Clock;
+
 #[derive(Debug, Clone)]
 pub struct Definitions {
     pub paths: Vec<PathBuf>,
     pub name: Option<String>,
     pub archives: Vec<PathBuf>,
+    pub clock: Option<Clock>,
+    // silence threshold in dBFS, for trimming music
+    pub trim: Option<f32>,
 
     pub endpoints: Vec<String>,
     pub zones: HashMap<String, Zone>,
@@ -54,22 +59,54 @@ impl Definitions {
             paths: vec![],
             name: None,
             archives: vec![],
+            clock: None,
+            trim: None,
             endpoints: vec![],
             zones: HashMap::new(),
         }
     }
 
     pub fn reload(&mut self) -> anyhow::Result<()> {
-        let mut new = Definitions::empty();
-        for path in self.paths.iter() {
-            new.merge(Definitions::load_one(path)?);
-        }
+        let mut new = Definitions::load_all(&self.paths)?;
         new.verify()?;
         std::mem::swap(&mut new.paths, &mut self.paths);
         *self = new;
         Ok(())
     }
 
+    // load these definitions and report every problem found, not just
+    // the first. fails only if they can't be read at all.
+    pub fn lint<PI, P>(paths: PI) -> anyhow::Result<Vec<anyhow::Error>>
+    where
+        PI: Iterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let paths: Vec<PathBuf> = paths.map(|p| p.as_ref().to_owned()).collect();
+        let defs = Definitions::load_all(&paths)?;
+        let mut problems = defs.problems();
+
+        for archive in defs.archives.iter() {
+            if !archive.exists() {
+                problems.push(anyhow::anyhow!("archive does not exist: {:?}", archive));
+            }
+        }
+
+        // the radio needs somewhere to start and somewhere else to go
+        if defs.endpoints.len() < 2 {
+            problems.push(anyhow::anyhow!("at least two endpoints are needed"));
+        }
+
+        Ok(problems)
+    }
+
+    fn load_all(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut new = Definitions::empty();
+        for path in paths.iter() {
+            new.merge(Definitions::load_one(path)?);
+        }
+        Ok(new)
+    }
+
     fn load_one(path: &PathBuf) -> anyhow::Result<Self> {
         let mut new = Definitions::empty();
         let base = path.parent().unwrap_or(Path::new("."));
@@ -81,7 +118,15 @@ impl Definitions {
 
         crate::Definitions::check_keys(
             data,
-            &["name", "include", "archives", "endpoints", "zones"],
+            &[
+                "name",
+                "include",
+                "archives",
+                "clock",
+                "trim",
+                "endpoints",
+                "zones",
+            ],
         )?;
 
         // read and merge includes first
@@ -100,6 +145,19 @@ impl Definitions {
             new.name = Some(name.to_owned());
         }
 
+        // read the game clock
+        if !data["clock"].is_badvalue() {
+            new.clock = Some(Self::parse_clock(&data["clock"])?);
+        }
+
+        // read the silence trimming threshold
+        if let Some(trim) = crate::Definitions::get_str(data, "trim")? {
+            new.trim = Some(
+                trim.parse()
+                    .map_err(|_| anyhow::anyhow!("bad trim threshold: {:?}", trim))?,
+            );
+        }
+
         // read in string lists
         new.archives.extend(
             Self::get_str_vec(data, "archives")?
@@ -155,54 +213,96 @@ impl Definitions {
         Ok(new)
     }
 
+    fn parse_clock(data: &StrictYaml) -> anyhow::Result<Clock> {
+        crate::Definitions::check_keys(data, &["speed", "start", "dawn", "dusk"])?;
+        let mut clock = Clock::default();
+        if let Some(speed) = crate::Definitions::get_str(data, "speed")? {
+            clock.speed = speed
+                .parse()
+                .map_err(|_| anyhow::anyhow!("bad clock speed: {:?}", speed))?;
+        }
+        if let Some(start) = crate::Definitions::get_str(data, "start")? {
+            clock.start = Some(Clock::parse_time_of_day(start)?);
+        }
+        if let Some(dawn) = crate::Definitions::get_str(data, "dawn")? {
+            clock.dawn = Clock::parse_time_of_day(dawn)?;
+        }
+        if let Some(dusk) = crate::Definitions::get_str(data, "dusk")? {
+            clock.dusk = Clock::parse_time_of_day(dusk)?;
+        }
+        Ok(clock)
+    }
+
     fn verify(&self) -> anyhow::Result<()> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    // everything wrong with how the zones fit together
+    fn problems(&self) -> Vec<anyhow::Error> {
+        let mut problems = vec![];
+
         // are all endpoints defined as zones?
         for endpoint in &self.endpoints {
             if !self.zones.contains_key(endpoint) {
-                anyhow::bail!("endpoint zone not found: {:?}", endpoint);
+                problems.push(anyhow::anyhow!("endpoint zone not found: {:?}", endpoint));
             }
         }
 
+        // go in order, so problems are reported the same way every time
+        let mut zones: Vec<&Zone> = self.zones.values().collect();
+        zones.sort_by(|a, b| a.name.cmp(&b.name));
+
         // are all connections and vias defined as zones?
-        for zone in self.zones.values() {
+        for zone in zones.iter() {
             for conn in &zone.connections {
                 if !self.zones.contains_key(&conn.destination) {
-                    anyhow::bail!("connection endpoint not found: {:?}", conn.destination);
+                    problems.push(anyhow::anyhow!(
+                        "connection endpoint not found: {:?}",
+                        conn.destination
+                    ));
                 }
                 if let Some(via) = &conn.via {
                     if !self.zones.contains_key(via) {
-                        anyhow::bail!("connection via not found: {:?}", via);
+                        problems.push(anyhow::anyhow!("connection via not found: {:?}", via));
                     }
                 }
             }
         }
 
         // are all connections reciprocated?
-        for zone in self.zones.values() {
+        for zone in zones.iter() {
             for conn in &zone.connections {
-                let recip = conn.flip(&zone);
-                let dest = self
-                    .zones
-                    .get(&conn.destination)
-                    .expect("could not find zone");
+                let recip = conn.flip(zone);
+                let Some(dest) = self.zones.get(&conn.destination) else {
+                    continue;
+                };
                 if !dest.connections.contains(&recip) {
-                    anyhow::bail!(
+                    problems.push(anyhow::anyhow!(
                         "flip connection {:?} -> {:?} via {:?} missing",
                         dest.name,
                         recip.destination,
                         recip.via
-                    );
+                    ));
                 }
             }
         }
 
-        Ok(())
+        problems
     }
 
     pub fn merge(&mut self, other: Self) {
         if self.name.is_none() {
             self.name = other.name;
         }
+        if self.clock.is_none() {
+            self.clock = other.clock;
+        }
+        if self.trim.is_none() {
+            self.trim = other.trim;
+        }
 
         self.archives.extend(other.archives);
         self.endpoints.extend(other.endpoints);
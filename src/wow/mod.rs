@@ -2,8 +2,10 @@ mod data;
 mod radio;
 mod definitions;
 mod ambient_scheduler;
+mod clock;
 
 pub use data::{Data, Sound, Soundscape, Area};
 pub use radio::Radio;
 pub use definitions::Definitions;
 pub use ambient_scheduler::AmbientScheduler;
+pub use clock::Clock;
@@ -1,50 +1,126 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 
-use crate::{Scheduler, source, Time, RandomMixer};
+use crate::control::{self, Command, ControlReceiver};
+use crate::playlist::{Entry, Playlist};
+use crate::{Scheduler, Segment, SegmentKind, source, Time, RandomMixer};
 use super::{AmbientScheduler, Definitions, Data, Area, Soundscape, Sound};
 
 pub struct Radio<F> {
     definitions: Definitions,
     scheduler: AmbientScheduler,
-    metadata_callback: F,
+    // shared with marks on the scheduler, which call it when heard
+    metadata_callback: Rc<RefCell<F>>,
+    control: Option<ControlReceiver>,
+    playlist: Playlist,
 
     data: Data,
     areacache: HashMap<String, Area>,
     last_played: Option<String>,
     r_zones: RandomMixer<String>,
-}
 
-macro_rules! set_metadata {
-    ($self:expr, $fmt:expr) =>
-        (($self.metadata_callback)(
-            format!(concat!("{} - ", $fmt),
-                    $self.definitions.name.as_deref().unwrap_or("Sprunk"))
-        ));
-    ($self:expr, $fmt:expr, $($arg:tt)*) =>
-        (($self.metadata_callback)(
-            format!(concat!("{} - ", $fmt),
-                    $self.definitions.name.as_deref().unwrap_or("Sprunk"),
-                    $($arg)*))
-        );
+    // where we are, and where we've been asked to go next
+    current_zone: Option<String>,
+    queued_zones: VecDeque<String>,
+
+    // game clock time of day at the start of the stream
+    clock_start: f32,
+    // is the current ambience the night one?
+    night: bool,
 }
 
-impl<F> Radio<F> where F: FnMut(String) {
+impl<F> Radio<F> where F: FnMut(Segment) + 'static {
     pub fn new<PI, P>(mut scheduler: Scheduler, paths: PI, metadata_callback: F) -> anyhow::Result<Self> where PI: Iterator<Item = P>, P: AsRef<std::path::Path> {
         Ok(Self {
             definitions: Definitions::open(paths)?,
             scheduler: AmbientScheduler::new(scheduler, Time::seconds(3.0)),
-            metadata_callback,
+            metadata_callback: Rc::new(RefCell::new(metadata_callback)),
+            control: None,
+            playlist: Playlist::new(),
 
             data: Data::new(),
             areacache: HashMap::new(),
             last_played: None,
             r_zones: RandomMixer::new(),
+
+            current_zone: None,
+            queued_zones: VecDeque::new(),
+
+            clock_start: 0.0,
+            night: false,
         })
     }
 
+    // take commands from here while playing
+    pub fn set_control(&mut self, control: ControlReceiver) {
+        self.playlist = control.playlist().clone();
+        self.control = Some(control);
+    }
+
+    fn segment(&self, zone: &str, title: Option<&str>) -> Segment {
+        let mut segment = Segment::new(self.definitions.name.as_deref().unwrap_or("Sprunk"), SegmentKind::Zone);
+        segment.zone = Some(zone.to_owned());
+        segment.title = title.map(|t| t.to_owned());
+        segment
+    }
+
+    fn update_requested(&mut self) {
+        let requested = self.queued_zones.iter().map(|z| Entry::new(self.segment(z, None), None)).collect();
+        self.playlist.set_requested(requested);
+    }
+
+    fn handle(&mut self, command: &Command) -> anyhow::Result<()> {
+        match command {
+            Command::Skip => {
+                self.scheduler.skip();
+                self.playlist.unschedule();
+            }
+            Command::Pause => self.scheduler.set_paused(true),
+            Command::Resume => self.scheduler.set_paused(false),
+            Command::Queue(query) => {
+                let zone = self.definitions.zones.keys().find(|z| z.eq_ignore_ascii_case(query)).ok_or_else(|| anyhow::anyhow!("no zone named {:?}", query))?;
+                self.queued_zones.push_back(zone.clone());
+                self.update_requested();
+            }
+            Command::Ad | Command::Id => anyhow::bail!("wow stations have no {:?}", command),
+        }
+        Ok(())
+    }
+
+    // wait until this time, handling commands meanwhile.
+    // false if what we were waiting for was skipped.
+    async fn wait(&mut self, time: Time) -> anyhow::Result<bool> {
+        loop {
+            let scheduler = &mut self.scheduler;
+            let control = &mut self.control;
+            let request = futures_lite::future::or(
+                async move { scheduler.wait(time).await.map(|_| None) },
+                async move { Ok(Some(control::next_request(control).await)) },
+            ).await?;
+
+            let Some(request) = request else {
+                return Ok(true);
+            };
+            let skipped = request.command == Command::Skip;
+            let result = self.handle(&request.command);
+            request.reply(result);
+            if skipped {
+                return Ok(false);
+            }
+        }
+    }
+
+    // wait until the next music is needed
+    async fn ready(&mut self) -> anyhow::Result<()> {
+        while !self.wait(self.scheduler.ready_time()).await? {}
+        Ok(())
+    }
+
     pub fn reload(&mut self) -> anyhow::Result<()> {
         self.definitions.reload()?;
         self.data.set_paths(self.definitions.archives.iter())?;
+        self.scheduler.set_trim(self.definitions.trim);
 
         self.areacache.clear();
         for zone in self.definitions.zones.values() {
@@ -62,25 +138,45 @@ impl<F> Radio<F> where F: FnMut(String) {
                 continue;
             }
 
-            let data = self.data.read_file(path)?;
-            self.scheduler.add_music(sound.volume, data).await?;
-            self.last_played = Some(path.clone());
-
             let file_name = path.rsplit_once('\\').map(|t| t.1);
             let file_stem = file_name.and_then(|name| name.rsplit_once('.').map(|t| t.0));
+            let mut segment = self.segment(&zone.name, file_stem);
+
+            self.ready().await?;
+            let data = self.data.read_file(path)?;
+            let (start, end) = self.scheduler.add_music(sound.volume, data)?;
+            self.last_played = Some(path.clone());
+            let samplerate = self.scheduler.samplerate();
+            segment.duration = Some(end.to_seconds(samplerate) - start.to_seconds(samplerate));
+            self.playlist.schedule(Entry::new(segment.clone(), Some(self.scheduler.system_time(start))));
+            // stamp it as started when it's heard, not when it's rendered
+            let playlist = self.playlist.clone();
+            let callback = self.metadata_callback.clone();
+            self.scheduler.mark(start, move || {
+                playlist.start();
+                (callback.borrow_mut())(segment)
+            });
+            if !self.wait(start).await? {
+                continue;
+            }
 
-            if let Some(name) = file_stem {
-                set_metadata!(self, "{} - {}", zone.name, name);
-            } else {
-                set_metadata!(self, "{}", zone.name);
+            // did we cross dusk or dawn during this zone?
+            if self.is_night() != self.night {
+                self.night = !self.night;
+                let soundscape = if self.night { &zone.night } else { &zone.day };
+                self.set_ambience(soundscape).await?;
             }
         }
 
         Ok(())
     }
 
-    pub async fn play_zone_soundscape(&mut self, zone: &Area, soundscape: &Soundscape) -> anyhow::Result<()> {
-        // set up the ambience
+    fn is_night(&self) -> bool {
+        let clock = self.definitions.clock.clone().unwrap_or_default();
+        clock.is_night(self.clock_start, self.scheduler.elapsed())
+    }
+
+    pub async fn set_ambience(&mut self, soundscape: &Soundscape) -> anyhow::Result<()> {
         if soundscape.ambience.items.is_empty() {
             self.scheduler.add_ambience(1.0, None).await?;
         } else {
@@ -89,6 +185,13 @@ impl<F> Radio<F> where F: FnMut(String) {
             self.scheduler.add_ambience(soundscape.ambience.volume, Some(ambience_data)).await?;
         }
 
+        Ok(())
+    }
+
+    pub async fn play_zone_soundscape(&mut self, zone: &Area, soundscape: &Soundscape) -> anyhow::Result<()> {
+        // set up the ambience
+        self.set_ambience(soundscape).await?;
+
         // play intro music (if any)
         self.play_sound_block(zone, &zone.intro).await?;
 
@@ -102,8 +205,10 @@ impl<F> Radio<F> where F: FnMut(String) {
         let zone = self.areacache.get(name).ok_or_else(|| anyhow::anyhow!("could not get zone: {:?}", name))?.clone();
         //println!("{:#?}", zone);
 
-        // FIXME day/night
-        self.play_zone_soundscape(&zone, &zone.day).await?;
+        self.current_zone = Some(name.to_owned());
+        self.night = self.is_night();
+        let soundscape = if self.night { &zone.night } else { &zone.day };
+        self.play_zone_soundscape(&zone, soundscape).await?;
 
         Ok(())
 
@@ -125,6 +230,11 @@ impl<F> Radio<F> where F: FnMut(String) {
 
         let mut current = self.definitions.zones.get(start).expect("could not get zone");
         for zone in &path_outline {
+            // head somewhere else if asked
+            if !self.queued_zones.is_empty() {
+                break;
+            }
+
             // look for a via connection and route through the via if found
             for conn in &current.connections {
                 if &conn.destination == zone {
@@ -149,6 +259,9 @@ impl<F> Radio<F> where F: FnMut(String) {
         self.reload()?;
         //println!("{:#?}", self.definitions);
 
+        // start the game clock
+        self.clock_start = self.definitions.clock.clone().unwrap_or_default().start();
+
         // make sure we don't loop forever looking for a unique pair
         assert!(self.definitions.endpoints.len() >= 2);
 
@@ -157,18 +270,20 @@ impl<F> Radio<F> where F: FnMut(String) {
         self.play_zone(&start).await?;
 
         loop {
-            // where are we going?
+            // where are we going? we may not have made it where we were headed
+            start = self.current_zone.clone().unwrap_or(start);
 
             // loop until we find an end that differs from the start
-            let end = loop {
+            let queued = self.queued_zones.pop_front().filter(|z| *z != start);
+            self.update_requested();
+            let end = if let Some(zone) = queued { zone } else { loop {
                 let end = self.r_zones.choose(self.definitions.endpoints.iter(), |z| z).expect("could not get end zone").clone();
                 if end != start {
                     break end;
                 }
-            };
+            } };
 
             self.path(&start, &end).await?;
-            start = end;
 
             // it is... mostly safe to ignore this error
             // it's possible to reload definitions but then fail to